@@ -0,0 +1,145 @@
+use crate::readback::{linear_to_srgb, srgb_to_linear};
+
+/// Sub-pixel offset of `--accumulate`'s subframe `index` in pixels, between -0.5 and 0.5. Follows
+/// the Halton (2, 3) sequence so any number of subframes covers the pixel evenly.
+pub fn jitter(index: u32) -> [f32; 2] {
+    // the sequence starts at 0 for both axes, skip that corner
+    [halton(index + 1, 2) - 0.5, halton(index + 1, 3) - 0.5]
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Averages jittered subframes into one anti-aliased image. The color channels are averaged in
+/// linear space so edges don't come out too dark.
+pub struct Accumulator {
+    sum: Vec<f32>,
+    /// Which subframes the current image already has, indexed by subframe
+    added: Vec<bool>,
+}
+impl Accumulator {
+    pub fn new(subframes: u32) -> Self {
+        Self {
+            sum: Vec::new(),
+            added: vec![false; subframes as usize],
+        }
+    }
+
+    pub fn subframes(&self) -> u32 {
+        self.added.len() as u32
+    }
+
+    /// Adds subframe `index` as sRGB encoded RGBA8 pixels, drawn with [`jitter`]`(index)`. A
+    /// subframe the current image already has is ignored, so every offset counts once.
+    pub fn add(&mut self, index: u32, rgba8: &[u8]) {
+        let Some(added) = self.added.get_mut(index as usize) else {
+            return;
+        };
+        if std::mem::replace(added, true) {
+            return;
+        }
+        self.sum.resize(rgba8.len(), 0.0);
+        for (i, (sum, value)) in self.sum.iter_mut().zip(rgba8).enumerate() {
+            let value = *value as f32 / 255.0;
+            *sum += if i % 4 == 3 {
+                value
+            } else {
+                srgb_to_linear(value)
+            };
+        }
+    }
+
+    /// Whether every subframe was added.
+    pub fn is_done(&self) -> bool {
+        self.added.iter().all(|added| *added)
+    }
+
+    /// The average of the added subframes as sRGB encoded RGBA8, starting over for the next
+    /// image.
+    pub fn take_average(&mut self) -> Vec<u8> {
+        let added = self.added.iter().filter(|added| **added).count().max(1) as f32;
+        let average = self
+            .sum
+            .iter()
+            .enumerate()
+            .map(|(i, sum)| {
+                let value = sum / added;
+                let encoded = if i % 4 == 3 {
+                    value
+                } else {
+                    linear_to_srgb(value)
+                };
+                (encoded.clamp(0.0, 1.0) * 255.0).round() as u8
+            })
+            .collect();
+        self.sum.clear();
+        self.added.fill(false);
+        average
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn halton_is_the_radical_inverse() {
+        assert_close(halton(1, 2), 0.5);
+        assert_close(halton(2, 2), 0.25);
+        assert_close(halton(3, 2), 0.75);
+        assert_close(halton(1, 3), 1.0 / 3.0);
+        assert_close(halton(2, 3), 2.0 / 3.0);
+        assert_close(halton(3, 3), 1.0 / 9.0);
+    }
+
+    #[test]
+    fn jitter_stays_within_the_pixel() {
+        let [x, y] = jitter(0);
+        assert_close(x, 0.0);
+        assert_close(y, 1.0 / 3.0 - 0.5);
+        for index in 0..256 {
+            let [x, y] = jitter(index);
+            assert!((-0.5..0.5).contains(&x), "x {x} of subframe {index}");
+            assert!((-0.5..0.5).contains(&y), "y {y} of subframe {index}");
+        }
+    }
+
+    #[test]
+    fn averages_in_linear_space() {
+        let mut accumulator = Accumulator::new(2);
+        accumulator.add(0, &[0, 0, 0, 255]);
+        assert!(!accumulator.is_done());
+        accumulator.add(1, &[255, 255, 255, 255]);
+        assert!(accumulator.is_done());
+        // half of linear white, a plain average of the encoded values would be 128
+        assert_eq!(accumulator.take_average(), [188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn counts_every_subframe_once() {
+        let mut accumulator = Accumulator::new(2);
+        accumulator.add(1, &[255, 255, 255, 255]);
+        accumulator.add(1, &[255, 255, 255, 255]);
+        accumulator.add(2, &[255, 255, 255, 255]);
+        assert!(!accumulator.is_done());
+        accumulator.add(0, &[0, 0, 0, 255]);
+        assert!(accumulator.is_done());
+        assert_eq!(accumulator.take_average(), [188, 188, 188, 255]);
+        assert!(!accumulator.is_done());
+    }
+}
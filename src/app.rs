@@ -0,0 +1,410 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::{Duration, Instant},
+};
+
+use glam::Vec3;
+use tracing::{info, warn};
+use vulkano::{
+    VulkanLibrary,
+    command_buffer::allocator::StandardCommandBufferAllocator,
+    device::QueueFlags,
+    instance::{Instance, InstanceCreateInfo},
+    memory::allocator::StandardMemoryAllocator,
+    swapchain::Surface,
+};
+use winit::{
+    dpi::PhysicalSize, event_loop::EventLoop, platform::run_on_demand::EventLoopExtRunOnDemand,
+};
+
+use crate::{
+    bench::Bench,
+    camera_path::CameraPath,
+    cli::Args,
+    config::MaterialValue,
+    connection::Connection,
+    diagnostics,
+    error::{SetupContext, SetupError},
+    export::ExportSocket,
+    fly_camera::{FlyCamera, FlyCameras},
+    frame::{Renderer, create_cme_swapchain},
+    frame_pattern::FramePattern,
+    gamepad::Gamepad,
+    gpu::{create_device, render_node, select_physical_device, select_queue_family},
+    grid::Grid,
+    headless::{Destination, PngSink},
+    model_watch::ModelWatcher,
+    no_interop,
+    orbit_camera::OrbitCamera,
+    raw_out::RawOut,
+    stardust::{StardustLoop, Target},
+    status::StatusServer,
+    stream::{Encoder, FALLBACK_VAAPI_DEVICE},
+    validation,
+    window::{Output, WinitApp},
+    yuv::{self, Nv12Converter},
+};
+
+/// How often a lost device is set up again before giving up, one that keeps getting lost won't
+/// recover.
+const MAX_DEVICE_RESTARTS: u32 = 3;
+
+/// Everything the binary does: connects, sets up the device and runs the window or headless
+/// output until shutdown. A lost device is set up again from scratch, up to
+/// [`MAX_DEVICE_RESTARTS`] times.
+pub async fn run(args: Args) -> Result<(), SetupError> {
+    info!("Hello, world!");
+    // winit can't create a second event loop, every restart runs on this one
+    let mut event_loop = if args.headless {
+        None
+    } else {
+        Some(EventLoop::new().vk_context("creating the event loop")?)
+    };
+    let mut restarts = 0;
+    loop {
+        match run_device(&args, event_loop.as_mut()).await {
+            Err(SetupError::DeviceLost) if restarts < MAX_DEVICE_RESTARTS => {
+                restarts += 1;
+                warn!(
+                    restarts,
+                    "setting up a new device to recover from the lost one"
+                );
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Connects and runs on a freshly created device until shutdown or until the device is lost.
+/// Everything set up here is dropped before returning, so a restart starts clean.
+async fn run_device(args: &Args, event_loop: Option<&mut EventLoop<()>>) -> Result<(), SetupError> {
+    let (connection, async_loop, saved_state) = match Connection::connect(args.hdr).await {
+        Err(err) if args.no_interop && err.is_interop() => return no_interop::run(err, event_loop),
+        result => result?,
+    };
+    let projection = args
+        .projection(saved_state.as_ref().map(|state| state.fov))
+        .map_err(SetupError::InvalidArgument)?;
+    let color_correction = args
+        .color_correction()
+        .map_err(SetupError::InvalidArgument)?;
+    let library = VulkanLibrary::new().vk_context("loading the vulkan library")?;
+
+    let mut enabled_extensions = match event_loop.as_deref() {
+        Some(event_loop) => {
+            Surface::required_extensions(event_loop).vk_context("querying surface extensions")?
+        }
+        None => Default::default(),
+    };
+    let validation = args.validation && validation::is_available(&library);
+    enabled_extensions.ext_debug_utils = validation;
+    // every color space but SRGB needs the extension
+    enabled_extensions.ext_swapchain_colorspace =
+        args.color_space.is_some() && library.supported_extensions().ext_swapchain_colorspace;
+    let instance = Instance::new(
+        library,
+        InstanceCreateInfo {
+            enabled_extensions,
+            enabled_layers: if validation {
+                vec![validation::VALIDATION_LAYER.to_owned()]
+            } else {
+                Vec::new()
+            },
+            ..Default::default()
+        },
+    )
+    .vk_context("creating the instance")?;
+    // kept alive until the end of run, after everything else using the instance is gone
+    let _messenger = validation
+        .then(|| validation::create_messenger(&instance))
+        .transpose()?;
+    let phys_dev =
+        match select_physical_device(&instance, &connection.render_dev, args.gpu_selector()) {
+            Err(err) if args.no_interop && err.is_interop() => {
+                return no_interop::run(err, event_loop);
+            }
+            result => result?,
+        };
+    if args.list_formats {
+        // --list-formats conflicts with --headless, so there is an event loop
+        let event_loop = event_loop.unwrap();
+        return diagnostics::list_formats(&connection, instance, phys_dev, event_loop);
+    }
+    let mut required_queue_flags = QueueFlags::TRANSFER;
+    if args.nv12() {
+        required_queue_flags |= QueueFlags::COMPUTE;
+    }
+    // the window's tone mapper and exposure brackets are render passes
+    if event_loop.is_some() || color_correction.is_some() {
+        required_queue_flags |= QueueFlags::GRAPHICS;
+    }
+    let queue_families = select_queue_family(
+        &phys_dev,
+        required_queue_flags,
+        args.nv12() || args.hdr || color_correction.is_some(),
+        event_loop.as_deref(),
+        args.queue_family,
+    )?;
+    let (dev, queue, present_queue) =
+        create_device(&phys_dev, queue_families, event_loop.is_some(), true)?;
+    let mut renderer = Renderer::new(
+        dev.clone(),
+        queue,
+        Arc::new(StandardCommandBufferAllocator::new(
+            dev.clone(),
+            Default::default(),
+        )),
+    );
+    if args.bench.is_some() {
+        renderer.timings = Some(Vec::new());
+    }
+    let memalloc = Arc::new(StandardMemoryAllocator::new_default(dev.clone()));
+    let view_layout = args.view_layout();
+    let eye_sizes = args.eye_sizes();
+    let output = Arc::<Mutex<Option<Output>>>::default();
+    let target = if args.headless {
+        let size = args.render_size().unwrap_or(PhysicalSize::new(1280, 720));
+        if args.nv12() && !yuv::supports_size(size) {
+            let flag = if args.yuv { "--yuv" } else { "--stream" };
+            return Err(SetupError::InvalidArgument(format!(
+                "{flag} needs a render width divisible by 4 and an even height, got {}x{}",
+                size.width, size.height
+            )));
+        }
+        let nv12 = args
+            .nv12()
+            .then(|| Nv12Converter::new(&dev, memalloc.clone(), size, view_layout.count()))
+            .transpose()?;
+        let frame_size = PhysicalSize::new(size.width * view_layout.count(), size.height);
+        let destination = match (&args.stream, &args.raw_out, &args.out) {
+            (Some(url), _, _) => {
+                let vaapi_device = args
+                    .vaapi_device
+                    .clone()
+                    .or_else(|| render_node(dev.physical_device()))
+                    .unwrap_or_else(|| {
+                        warn!(
+                            device = FALLBACK_VAAPI_DEVICE,
+                            "the gpu doesn't report its render node, encoding on the first one"
+                        );
+                        PathBuf::from(FALLBACK_VAAPI_DEVICE)
+                    });
+                Destination::Stream(Encoder::spawn(
+                    url,
+                    &args.stream_codec,
+                    &vaapi_device,
+                    frame_size,
+                    &args.sdp,
+                )?)
+            }
+            (None, Some(raw_out), _) => Destination::Raw(RawOut::open(raw_out, frame_size)?),
+            (None, None, Some(out)) if FramePattern::is_pattern(out) => {
+                Destination::Pattern(FramePattern::parse(out)?)
+            }
+            (None, None, Some(out)) if args.once => Destination::File(out.clone()),
+            (None, None, Some(out_dir)) => Destination::Directory(out_dir.clone()),
+            // clap requires one of them with --headless
+            (None, None, None) => unreachable!(),
+        };
+        let cme_swapchain = create_cme_swapchain(
+            &connection,
+            &dev,
+            connection.cme_format,
+            size,
+            view_layout.count(),
+        )?;
+        Target::Headless(
+            PngSink::new(
+                cme_swapchain,
+                memalloc.clone(),
+                size,
+                view_layout.count(),
+                destination,
+                args.frames,
+                connection.cme_format,
+                nv12,
+            )?
+            .with_color_correction(color_correction)?
+            .with_accumulation(args.accumulate)
+            .with_alpha(args.alpha),
+        )
+    } else {
+        Target::Window(output.clone())
+    };
+    let mut fly_camera = match (&args.camera, saved_state) {
+        (Some(camera), _) => camera.fly_camera(),
+        (None, Some(state)) => state.fly_camera(),
+        (None, None) => FlyCamera::new(Vec3::new(0.0, 0.2, 0.2), -90f32.to_radians(), 0.0),
+    };
+    args.apply_pose(&mut fly_camera);
+    fly_camera.smooth = args.smooth_camera;
+    let orbit_camera = args.orbit.then(|| {
+        let orbit_camera = OrbitCamera::looking_at(args.pivot(), fly_camera.position);
+        orbit_camera.apply(&mut fly_camera);
+        orbit_camera
+    });
+    let mut stardust_loop = StardustLoop::new(
+        renderer,
+        target,
+        instance.clone(),
+        connection,
+        projection,
+        FlyCameras::new(fly_camera, args.grid.count()),
+    );
+    stardust_loop.view_layout = view_layout;
+    stardust_loop.per_eye = eye_sizes.is_some();
+    stardust_loop.view_offset = Vec3::from_slice(&args.view_offset);
+    stardust_loop.model = args.model.clone();
+    stardust_loop.model_part = args.model_part.clone();
+    stardust_loop.material = args.material.clone();
+    // F5 toggles it from there, so it isn't set again with the rest
+    if let Some(MaterialValue::Bool(unlit)) = stardust_loop.material.remove("unlit") {
+        stardust_loop.unlit.store(unlit, Ordering::Relaxed);
+    }
+    stardust_loop.model_watcher = args
+        .watch_model
+        .then(|| ModelWatcher::new(&args.model))
+        .transpose()?;
+    stardust_loop.preview = !args.no_preview;
+    stardust_loop.hdr = args.hdr;
+    stardust_loop.max_fps = args.max_fps;
+    stardust_loop.frame_timeout = args.frame_timeout;
+    stardust_loop.reconnect_on_stall = args.reconnect_on_stall;
+    stardust_loop.export = args
+        .export_socket
+        .clone()
+        .map(ExportSocket::bind)
+        .transpose()?;
+    stardust_loop.bench = args
+        .bench
+        .map(|frames| Bench::new(frames, args.bench_csv.clone()));
+    let shutdown = stardust_loop.shutdown.clone();
+    stardust_loop.gamepad = args.gamepad.then(|| Gamepad::spawn(shutdown.clone()));
+    stardust_loop.camera_path = args
+        .path
+        .as_deref()
+        .map(|path| CameraPath::load(path, args.loop_path))
+        .transpose()?;
+    stardust_loop.bracket_count = args.bracket_count;
+    stardust_loop.bracket_stops = args.bracket_stops;
+    stardust_loop.color_correction = color_correction;
+    stardust_loop.blur_fps = args.blur_fps;
+    stardust_loop.skip_policy = args.skip_policy;
+    stardust_loop.log_timeline = args.log_timeline;
+    stardust_loop.deadline = args.duration.map(|duration| Instant::now() + duration);
+    // awaited before returning so a restart can bind the port again
+    let status_task = match args.status_port {
+        Some(port) => {
+            let server = StatusServer::bind(port).await?;
+            let status = stardust_loop.status.clone();
+            Some(tokio::spawn(server.run(status, shutdown.clone())))
+        }
+        None => None,
+    };
+    // the window's input sets these
+    let connection = stardust_loop.connection.clone();
+    let fly_cameras = stardust_loop.fly_cameras.clone();
+    let zoom = stardust_loop.zoom.clone();
+    let screenshot = stardust_loop.screenshot.clone();
+    let bracket = stardust_loop.bracket.clone();
+    let paused = stardust_loop.paused.clone();
+    let blurred = stardust_loop.blurred.clone();
+    let show_frustum = stardust_loop.show_frustum.clone();
+    let dump_matrices = stardust_loop.dump_matrices.clone();
+    let unlit = stardust_loop.unlit.clone();
+    let stardust_task = tokio::spawn(stardust_loop.run(async_loop));
+    let Some(event_loop) = event_loop else {
+        let joined = stardust_task.await;
+        shutdown.cancel();
+        if let Some(status_task) = status_task {
+            let _ = status_task.await;
+        }
+        let result = joined
+            .map_err(SetupError::TaskPanicked)
+            .and_then(|result| result);
+        if result.is_ok() {
+            info!("shut down cleanly");
+        }
+        return result;
+    };
+    let result = tokio::task::block_in_place(|| {
+        let mut winit_app = WinitApp {
+            output,
+            dev,
+            queue_families,
+            present_queue,
+            memalloc,
+            screenshot,
+            bracket,
+            replay_frames: args.replay_frames(),
+            paused,
+            pause_on_blur: args.pause_on_blur,
+            blurred,
+            show_frustum,
+            dump_matrices,
+            unlit,
+            preview: !args.no_preview,
+            looping_path: args.loop_path,
+            instance,
+            connection,
+            fly_cameras,
+            orbit_camera,
+            zoom,
+            // every eye is a cell of its own
+            views: if eye_sizes.is_some() {
+                1
+            } else {
+                view_layout.count()
+            },
+            grid: if eye_sizes.is_some() {
+                Grid {
+                    columns: 2,
+                    rows: 1,
+                }
+            } else {
+                args.grid
+            },
+            scaling: args.scaling,
+            flip: args.flip,
+            transparent: args.transparent,
+            clear_color: args.clear_color(),
+            title_stats: args.title_stats,
+            grab_cursor: args.grab_cursor,
+            look_at: args.look_at(),
+            monitor: args.monitor,
+            fullscreen: args.fullscreen,
+            overlay_window: args.overlay_window,
+            hdr: args.hdr,
+            color_correction,
+            render_size: args.render_size(),
+            supersample: args.supersample,
+            eye_sizes,
+            present_modes: args.present_modes(),
+            image_count: args.image_count,
+            color_space: args.color_space.map(Into::into),
+            acquire_timeout: Duration::from_millis(args.acquire_timeout_ms),
+            shutdown: shutdown.clone(),
+            error: None,
+        };
+        let result = event_loop
+            .run_app_on_demand(&mut winit_app)
+            .vk_context("running the event loop");
+        result.and(winit_app.error.map_or(Ok(()), Err))
+    });
+    shutdown.cancel();
+    // joined before propagating a panic so the status server still shuts down
+    let joined = stardust_task.await;
+    if let Some(status_task) = status_task {
+        let _ = status_task.await;
+    }
+    let result = result.and(
+        joined
+            .map_err(SetupError::TaskPanicked)
+            .and_then(|result| result),
+    );
+    if result.is_ok() {
+        info!("shut down cleanly");
+    }
+    result
+}
@@ -0,0 +1,93 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::frame::RenderTimings;
+
+/// One rendered cell of a benchmarked frame.
+struct Row {
+    frame: u64,
+    cell: usize,
+    /// Wall-clock time since the previous benchmarked frame
+    frame_time: Option<Duration>,
+    timings: RenderTimings,
+}
+
+/// Measures the Dmatex path for `--bench`: how long preparing the image, submitting and the GPU
+/// reaching the release point take, next to the wall-clock frame time. Written as CSV once
+/// enough frames were rendered.
+pub struct Bench {
+    frames: u64,
+    path: PathBuf,
+    rows: Vec<Row>,
+    rendered: u64,
+    last_frame: Option<Instant>,
+}
+impl Bench {
+    pub fn new(frames: u64, path: PathBuf) -> Self {
+        info!(frames, path = %path.display(), "benchmarking");
+        Self {
+            frames,
+            path,
+            rows: Vec::new(),
+            rendered: 0,
+            last_frame: None,
+        }
+    }
+
+    /// Takes the timings of every cell rendered this frame.
+    pub fn record(&mut self, timings: Vec<RenderTimings>) {
+        let now = Instant::now();
+        let frame_time = self
+            .last_frame
+            .replace(now)
+            .map(|last_frame| now - last_frame);
+        for (cell, timings) in timings.into_iter().enumerate() {
+            self.rows.push(Row {
+                frame: self.rendered,
+                cell,
+                frame_time,
+                timings,
+            });
+        }
+        self.rendered += 1;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.rendered >= self.frames
+    }
+
+    /// Writes one line per rendered cell, all times in milliseconds. The first frame has no
+    /// frame time.
+    pub fn write(&self) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        writeln!(file, "frame,cell,frame_ms,prepare_ms,submit_ms,gpu_ms")?;
+        for row in &self.rows {
+            let frame_ms = row
+                .frame_time
+                .map(|frame_time| format!("{:.3}", ms(frame_time)))
+                .unwrap_or_default();
+            writeln!(
+                file,
+                "{},{},{frame_ms},{:.3},{:.3},{:.3}",
+                row.frame,
+                row.cell,
+                ms(row.timings.prepare),
+                ms(row.timings.submit),
+                ms(row.timings.gpu),
+            )?;
+        }
+        file.flush()?;
+        info!(path = %self.path.display(), "wrote the benchmark results");
+        Ok(())
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
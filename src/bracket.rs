@@ -0,0 +1,61 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::info;
+
+use crate::tone_map::ColorCorrection;
+
+/// One frame of a bracket, read back with its own exposure.
+#[derive(Debug, Clone)]
+pub struct BracketShot {
+    pub correction: ColorCorrection,
+    pub path: PathBuf,
+}
+
+/// An exposure bracket for merging into an HDR image offline, captured one frame per exposure.
+/// The exposures are spread evenly in stops around the base exposure.
+pub struct Bracket {
+    shots: Vec<BracketShot>,
+    next: usize,
+}
+impl Bracket {
+    pub fn new(count: u32, stops: f32, base: ColorCorrection) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let shots = (0..count)
+            .map(|i| {
+                let ev = (i as f32 - (count - 1) as f32 / 2.0) * stops;
+                BracketShot {
+                    correction: ColorCorrection {
+                        exposure: base.exposure * ev.exp2(),
+                        ..base
+                    },
+                    path: PathBuf::from(format!(
+                        "bracket-{}.{:03}-{i}_ev{ev:+.1}.png",
+                        timestamp.as_secs(),
+                        timestamp.subsec_millis()
+                    )),
+                }
+            })
+            .collect();
+        info!(count, stops, "capturing an exposure bracket");
+        Self { shots, next: 0 }
+    }
+
+    /// The shot the next rendered frame should be read back as, until it's [`Self::advance`]d.
+    pub fn shot(&self) -> Option<&BracketShot> {
+        self.shots.get(self.next)
+    }
+
+    /// Moves on once a frame was rendered with the current shot.
+    pub fn advance(&mut self) {
+        self.next += 1;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.shots.len()
+    }
+}
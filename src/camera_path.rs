@@ -0,0 +1,171 @@
+use std::{error::Error, fs, path::Path};
+
+use glam::{EulerRot, Quat, Vec3};
+use serde::Deserialize;
+
+use crate::{error::SetupError, fly_camera::FlyCamera};
+
+/// A pose the camera passes through, as stored in the `--path` JSON array.
+#[derive(Debug, Deserialize)]
+struct Keyframe {
+    /// Seconds since the start of the path
+    time: f32,
+    position: [f32; 3],
+    /// Yaw, pitch and roll in degrees
+    #[serde(default)]
+    rotation: [f32; 3],
+}
+
+/// Moves the primary camera along keyframed poses for repeatable captures, easing in and out of
+/// every keyframe's position and slerping the rotation.
+pub struct CameraPath {
+    keyframes: Vec<(f32, Vec3, Quat)>,
+    looping: bool,
+    /// Seconds of frame time spent on the path so far
+    time: f32,
+    finished: bool,
+}
+impl CameraPath {
+    pub fn load(path: &Path, looping: bool) -> Result<Self, SetupError> {
+        fs::read_to_string(path)
+            .map_err(Into::into)
+            .and_then(|text| Self::parse(&text, looping))
+            .map_err(|err| SetupError::CameraPath(path.to_path_buf(), err))
+    }
+
+    /// Parses the `--path` JSON array.
+    fn parse(text: &str, looping: bool) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let keyframes: Vec<Keyframe> = serde_json::from_str(text)?;
+        if keyframes.is_empty() {
+            return Err("no keyframes".into());
+        }
+        if keyframes.windows(2).any(|pair| pair[1].time < pair[0].time) {
+            return Err("keyframe times must not decrease".into());
+        }
+        Ok(Self {
+            keyframes: keyframes
+                .iter()
+                .map(|keyframe| {
+                    let [yaw, pitch, roll] = keyframe.rotation.map(f32::to_radians);
+                    (
+                        keyframe.time,
+                        Vec3::from(keyframe.position),
+                        Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll),
+                    )
+                })
+                .collect(),
+            looping,
+            time: 0.0,
+            finished: false,
+        })
+    }
+
+    /// Advances the path by the frame's `delta` and moves `fly_camera` to the new pose. Once a
+    /// path without looping has ended the camera is left alone so it can be flown again.
+    pub fn apply(&mut self, delta: f32, fly_camera: &mut FlyCamera) {
+        if self.finished {
+            return;
+        }
+        let duration = self.keyframes.last().unwrap().0;
+        self.time += delta;
+        if self.looping && duration > 0.0 {
+            self.time %= duration;
+        } else if self.time >= duration {
+            self.time = duration;
+            self.finished = true;
+        }
+        let (position, rotation) = self.sample(self.time);
+        fly_camera.set_pose(position, rotation);
+    }
+
+    fn sample(&self, time: f32) -> (Vec3, Quat) {
+        let next = self
+            .keyframes
+            .iter()
+            .position(|(keyframe_time, _, _)| *keyframe_time > time);
+        match next {
+            Some(0) => {
+                let (_, position, rotation) = self.keyframes[0];
+                (position, rotation)
+            }
+            Some(next) => {
+                let (from_time, from_position, from_rotation) = self.keyframes[next - 1];
+                let (to_time, to_position, to_rotation) = self.keyframes[next];
+                let t = (time - from_time) / (to_time - from_time);
+                let eased = t * t * (3.0 - 2.0 * t);
+                (
+                    from_position.lerp(to_position, eased),
+                    from_rotation.slerp(to_rotation, t),
+                )
+            }
+            None => {
+                let (_, position, rotation) = *self.keyframes.last().unwrap();
+                (position, rotation)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = r#"[
+        {"time": 0.0, "position": [0, 0, 0]},
+        {"time": 2.0, "position": [2, 0, 0], "rotation": [90, 0, 0]}
+    ]"#;
+
+    fn assert_close(actual: Vec3, expected: Vec3) {
+        assert!(
+            actual.abs_diff_eq(expected, 1e-5),
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn eases_the_position_and_slerps_the_rotation() {
+        let path = CameraPath::parse(PATH, false).unwrap();
+        let (position, rotation) = path.sample(1.0);
+        assert_close(position, Vec3::new(1.0, 0.0, 0.0));
+        assert!(rotation.angle_between(Quat::from_rotation_y(45f32.to_radians())) < 1e-4);
+        // smoothstep of a quarter of the way
+        assert_close(path.sample(0.5).0, Vec3::new(0.3125, 0.0, 0.0));
+    }
+
+    #[test]
+    fn holds_the_ends() {
+        let path = CameraPath::parse(PATH, false).unwrap();
+        assert_close(path.sample(-1.0).0, Vec3::ZERO);
+        assert_close(path.sample(5.0).0, Vec3::new(2.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn looping_wraps_around() {
+        let mut path = CameraPath::parse(PATH, true).unwrap();
+        let mut camera = FlyCamera::new(Vec3::ZERO, 0.0, 0.0);
+        path.apply(2.5, &mut camera);
+        assert_close(camera.position, Vec3::new(0.3125, 0.0, 0.0));
+    }
+
+    #[test]
+    fn hands_the_camera_back_once_finished() {
+        let mut path = CameraPath::parse(PATH, false).unwrap();
+        let mut camera = FlyCamera::new(Vec3::ZERO, 0.0, 0.0);
+        path.apply(3.0, &mut camera);
+        assert_close(camera.position, Vec3::new(2.0, 0.0, 0.0));
+        assert!((camera.yaw - 90f32.to_radians()).abs() < 1e-4);
+        camera.position = Vec3::Y;
+        path.apply(0.1, &mut camera);
+        assert_close(camera.position, Vec3::Y);
+    }
+
+    #[test]
+    fn rejects_invalid_keyframes() {
+        assert!(CameraPath::parse("[]", false).is_err());
+        let decreasing = r#"[
+            {"time": 1.0, "position": [0, 0, 0]},
+            {"time": 0.5, "position": [1, 0, 0]}
+        ]"#;
+        assert!(CameraPath::parse(decreasing, false).is_err());
+    }
+}
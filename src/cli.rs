@@ -0,0 +1,741 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use glam::Vec3;
+use serde::Deserialize;
+use stardust_xr_fusion::{project_local_resources, values::ResourceID};
+use tracing::{level_filters::LevelFilter, warn};
+use tracing_subscriber::EnvFilter;
+use vulkano::swapchain::{ColorSpace, PresentMode};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    config::{CameraPose, Config, MaterialValue},
+    error::SetupError,
+    fly_camera::FlyCamera,
+    frame::{Flip, Scaling},
+    gpu::GpuSelector,
+    grid::Grid,
+    projection::Projection,
+    readback::AlphaMode,
+    replay::DEFAULT_REPLAY_FPS,
+    tone_map::ColorCorrection,
+    views::ViewLayout,
+    window::Corner,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// TOML file with defaults for the options below, flags passed here override it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Vertical field of view in degrees [default: the saved fov, or 60]
+    #[arg(long)]
+    pub fov: Option<f32>,
+    /// Distance of the near plane, passed to the projection as-is
+    #[arg(long, default_value_t = 300.0)]
+    pub near: f32,
+    /// Distance of the far plane, passed to the projection as-is
+    #[arg(long, default_value_t = 0.003)]
+    pub far: f32,
+    /// Use a reverse-Z depth buffer, required when near > far
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub reverse_z: bool,
+    /// Width of a rendered pixel relative to its height for anamorphic output, e.g. 2 renders
+    /// twice the horizontal view into the same resolution. The window scales by pixel counts, so
+    /// its preview looks squeezed unless --scaling stretches it back out
+    #[arg(long, default_value_t = 1.0)]
+    pub pixel_aspect: f32,
+    /// Shift the frustum off-axis by these fractions of the image's width and height, as x,y.
+    /// Together with --view-offset this gives the sheared frustums of portals and CAVE walls
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 2,
+        allow_negative_numbers = true,
+        default_values_t = [0.0, 0.0]
+    )]
+    pub lens_shift: Vec<f32>,
+    /// Move the rendered views relative to the camera by x,y,z meters
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 3,
+        allow_negative_numbers = true,
+        default_values_t = [0.0, 0.0, 0.0]
+    )]
+    pub view_offset: Vec<f32>,
+    /// Use an orthographic projection instead of a perspective one, e.g. to capture flat panels
+    #[arg(long)]
+    pub ortho: bool,
+    /// Visible height in meters with --ortho, the width follows the aspect ratio
+    #[arg(long, default_value_t = 1.0, requires = "ortho")]
+    pub ortho_size: f32,
+    /// Print the dmatex formats supported by the server and the window surface's formats and
+    /// present modes, then exit
+    #[arg(long, conflicts_with = "headless")]
+    pub list_formats: bool,
+    /// If Dmatex images can't be shared with the server, show a placeholder window reporting
+    /// the failure instead of exiting
+    #[arg(long, conflicts_with_all = ["headless", "list_formats"])]
+    pub no_interop: bool,
+    /// Render without a window, writing every frame to --out as a PNG, streaming it with --stream
+    /// or writing it raw with --raw-out
+    #[arg(long, requires = "destination")]
+    pub headless: bool,
+    /// Directory headless frames are written to, or the PNG file with --once and --accumulate.
+    /// A pattern like frame_{:06}.png names every frame after its index instead
+    #[arg(long, group = "destination")]
+    pub out: Option<PathBuf>,
+    /// Render a single frame headless, write it to the --out file and exit, e.g. for thumbnails
+    #[arg(
+        long,
+        requires = "out",
+        conflicts_with_all = ["stream", "yuv", "frames", "list_formats"]
+    )]
+    pub once: bool,
+    /// Like --once, but averages this many subframes with sub-pixel jitter into an anti-aliased
+    /// still. The camera doesn't move meanwhile
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(2..),
+        requires = "out",
+        conflicts_with_all = ["stream", "yuv", "frames", "list_formats", "path"]
+    )]
+    pub accumulate: Option<u32>,
+    /// Stream headless frames over RTP to udp://host:port by piping them into ffmpeg, the SDP
+    /// for viewers is written to --sdp. Has the same size requirements as --yuv
+    #[arg(long, group = "destination", requires = "headless")]
+    pub stream: Option<String>,
+    /// Write headless frames as tightly packed RGBA8 to this file or named pipe, or to stdout
+    /// with "-", e.g. to pipe them into ffmpeg. Logs go to stderr then
+    #[arg(
+        long,
+        group = "destination",
+        requires = "headless",
+        conflicts_with = "yuv"
+    )]
+    pub raw_out: Option<PathBuf>,
+    /// ffmpeg encoder used for --stream, vaapi encoders upload the frames to the gpu first
+    #[arg(long, default_value = "h264_vaapi", requires = "stream")]
+    pub stream_codec: String,
+    /// DRM render node the vaapi encoders of --stream run on [default: the render node of the
+    /// gpu rendering the frames]
+    #[arg(long, requires = "stream")]
+    pub vaapi_device: Option<PathBuf>,
+    /// Where the SDP describing --stream is written
+    #[arg(long, default_value = "stream.sdp", requires = "stream")]
+    pub sdp: PathBuf,
+    /// Write headless frames as raw NV12 (BT.709, limited range) instead of PNG, the render width
+    /// has to be divisible by 4 and the height by 2
+    #[arg(long, requires = "headless")]
+    pub yuv: bool,
+    /// Alpha of headless PNG and raw frames. Both are sRGB encoded RGBA, 8 bits per channel in
+    /// that order, premult keeps the color multiplied by alpha as rendered, straight divides it
+    /// out. The window ignores alpha unless --transparent
+    #[arg(long, value_enum, default_value_t = AlphaMode::Premult, requires = "headless")]
+    pub alpha: AlphaMode,
+    /// Stop after this many frames in headless mode
+    #[arg(long, requires = "headless")]
+    pub frames: Option<u64>,
+    /// Run for this long, like 10s, 500ms or 2m, then shut down cleanly
+    #[arg(long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+    /// Width of each rendered view, defaults to following the window or 1280 when headless
+    #[arg(long, requires = "render_height")]
+    pub render_width: Option<u32>,
+    /// Height of each rendered view, defaults to following the window or 720 when headless
+    #[arg(long, requires = "render_width")]
+    pub render_height: Option<u32>,
+    /// Render each view at this multiple of its size in the window and downsample it when
+    /// blitting, for cheap anti-aliasing. Lowered if the views would exceed the gpu's image size
+    /// limit
+    #[arg(
+        long,
+        value_parser = parse_supersample,
+        conflicts_with_all = ["headless", "render_width", "left_res"]
+    )]
+    pub supersample: Option<f32>,
+    /// Index of the gpu to render with, as listed by vulkan
+    #[arg(long, conflicts_with = "gpu_name")]
+    pub gpu: Option<usize>,
+    /// Case insensitive substring of the name of the gpu to render with
+    #[arg(long)]
+    pub gpu_name: Option<String>,
+    /// Index of the queue family to submit to instead of picking one, it still has to support
+    /// every enabled pass. If it can't present, another family presents the window
+    #[arg(long)]
+    pub queue_family: Option<u32>,
+    /// Render a left and right eye view side by side
+    #[arg(long)]
+    pub stereo: bool,
+    /// Interpupillary distance in meters used with --stereo
+    #[arg(long, default_value_t = 0.063, requires = "stereo")]
+    pub ipd: f32,
+    /// Render the left eye at this size like 1920x1080, independently of the right eye. Each eye
+    /// gets its own CME image, composited into one half of the window
+    #[arg(
+        long,
+        value_parser = parse_resolution,
+        requires_all = ["stereo", "right_res"],
+        conflicts_with_all = ["headless", "grid", "render_width"]
+    )]
+    pub left_res: Option<PhysicalSize<u32>>,
+    /// Render the right eye at this size, see --left-res
+    #[arg(long, value_parser = parse_resolution, requires = "left_res")]
+    pub right_res: Option<PhysicalSize<u32>>,
+    /// Sync presentation to the display refresh rate, this is the default
+    #[arg(long, overrides_with = "no_vsync")]
+    pub vsync: bool,
+    /// Present as fast as possible, using immediate or mailbox presentation
+    #[arg(long, overrides_with = "vsync")]
+    pub no_vsync: bool,
+    /// Present mode of the window swapchain, overrides --vsync/--no-vsync
+    #[arg(long, value_enum, conflicts_with = "no_vsync")]
+    pub present_mode: Option<PresentModeArg>,
+    /// Request at least this many window swapchain images, e.g. 3 for triple buffering to
+    /// reduce stalls with mailbox presentation. Clamped to what the surface supports. The CME
+    /// swapchains' image count is chosen by stardust-xr-cme and can't be set from the client
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "headless")]
+    pub image_count: Option<u32>,
+    /// Color space of the window swapchain. The rendered values are presented as they are, an
+    /// HDR color space only changes how the compositor interprets them
+    #[arg(long, value_enum, conflicts_with = "headless")]
+    pub color_space: Option<ColorSpaceArg>,
+    /// Warn when the server delivers no frame for this many seconds
+    #[arg(long, value_parser = parse_seconds)]
+    pub frame_timeout: Option<Duration>,
+    /// Reconnect when --frame-timeout passes without a frame
+    #[arg(long, requires = "frame_timeout")]
+    pub reconnect_on_stall: bool,
+    /// Serve the FPS, frame count, connection status and resolution as JSON over HTTP on this
+    /// localhost port
+    #[arg(long)]
+    pub status_port: Option<u16>,
+    /// Unix socket other local processes can connect to for the dmatex id and release point of
+    /// every submitted frame. Consumers sample the feed through the same stardust server with a
+    /// Dmatex material parameter, no file descriptors are passed
+    #[arg(long)]
+    pub export_socket: Option<PathBuf>,
+    /// Milliseconds to wait for a window image before skipping the frame
+    #[arg(long, default_value_t = 1000)]
+    pub acquire_timeout_ms: u64,
+    /// Render at most this many frames per second, e.g. to save power on a small preview. Frames
+    /// the server delivers early are held until they're due, so the rendered ones are evenly
+    /// spaced
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_fps: Option<u32>,
+    /// Stop rendering while the window is unfocused to save power, Pings are still answered
+    #[arg(long, conflicts_with = "headless")]
+    pub pause_on_blur: bool,
+    /// Keep rendering at this frame rate while unfocused instead of stopping, see
+    /// --pause-on-blur
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), requires = "pause_on_blur")]
+    pub blur_fps: Option<u32>,
+    /// What to do with frames the server delivers while the GPU is still busy with the last one
+    #[arg(long, value_enum, default_value_t = SkipPolicy::Queue)]
+    pub skip_policy: SkipPolicy,
+    /// Render this many frames measuring image preparation, submit and GPU latency of the
+    /// Dmatex path, then write them to --bench-csv and exit
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub bench: Option<u64>,
+    #[arg(long, default_value = "bench.csv", requires = "bench")]
+    pub bench_csv: PathBuf,
+    /// How the render is scaled into the window when their aspect ratios differ
+    #[arg(long, value_enum, default_value_t = Scaling::Stretch)]
+    pub scaling: Scaling,
+    /// Mirror every view in the window horizontally, vertically or both
+    #[arg(long, value_enum)]
+    pub flip: Option<Flip>,
+    /// Show the render with a transparent background where its alpha is below 1, if the
+    /// compositor supports premultiplied alpha
+    #[arg(long, conflicts_with = "headless")]
+    pub transparent: bool,
+    /// Fill the window where the render doesn't cover it with this linear r,g,b,a color, e.g.
+    /// the bars of --scaling fit. Alpha only shows with --transparent [default: 0,0,0,1, or
+    /// 0,0,0,0 with --transparent]
+    #[arg(long, value_delimiter = ',', num_args = 4, conflicts_with = "headless")]
+    pub clear_color: Option<Vec<f32>>,
+    /// Show the frame rate and resolution in the window title, updated every second
+    #[arg(long, conflicts_with = "headless")]
+    pub title_stats: bool,
+    /// Grab and hide the cursor while looking around with the right mouse button, released
+    /// again by escape or when the window loses focus
+    #[arg(long, conflicts_with = "headless")]
+    pub grab_cursor: bool,
+    /// Ease the camera's movement and mouse look in and out, integrated over every frame's delta
+    /// instead of jumping with each input event
+    #[arg(long)]
+    pub smooth_camera: bool,
+    /// Show the preview as a small borderless always-on-top window in this corner of the
+    /// primary monitor, like a picture-in-picture. Compositors may ignore the position and level,
+    /// Wayland ones always do
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "top-right",
+        conflicts_with = "headless"
+    )]
+    pub overlay_window: Option<Corner>,
+    /// Place the window on this monitor, counting from 0 in the order the compositor lists them
+    #[arg(long, conflicts_with = "headless")]
+    pub monitor: Option<usize>,
+    /// Start fullscreen on --monitor, or on the primary monitor without it. F11 still toggles
+    #[arg(long, conflicts_with_all = ["headless", "overlay_window"])]
+    pub fullscreen: bool,
+    /// Split the window into a grid of cameras like 2x2, Tab cycles which one gets the input
+    #[arg(long, default_value = "1x1", conflicts_with = "headless")]
+    pub grid: Grid,
+    /// Starting camera position as x,y,z, overriding the saved and configured one
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 3,
+        allow_negative_numbers = true
+    )]
+    pub pos: Option<Vec<f32>>,
+    /// Starting camera rotation as yaw,pitch,roll in degrees, overriding the saved and
+    /// configured one
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 3,
+        allow_negative_numbers = true
+    )]
+    pub rot: Option<Vec<f32>>,
+    /// Point the starting camera at this x,y,z instead of setting its rotation, L aims it at
+    /// the point again
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 3,
+        allow_negative_numbers = true,
+        conflicts_with_all = ["rot", "orbit"]
+    )]
+    pub look_at: Option<Vec<f32>>,
+    /// JSON array of keyframes like {"time": 2.0, "position": [0, 1, 2], "rotation": [90, 0, 0]}
+    /// to move the primary camera along, with rotation as yaw,pitch,roll in degrees
+    #[arg(long)]
+    pub path: Option<PathBuf>,
+    /// Restart --path from its first keyframe once it ends instead of handing the camera back
+    #[arg(long = "loop", requires = "path")]
+    pub loop_path: bool,
+    /// Start in orbit mode, rotating around --pivot. O toggles between orbit and fly mode
+    #[arg(long)]
+    pub orbit: bool,
+    /// Fly the camera with a gamepad: the left stick moves, the right stick looks, the right
+    /// trigger speeds up and the left trigger and bumper zoom in and out
+    #[arg(long)]
+    pub gamepad: bool,
+    /// Point the orbit camera rotates around, as x,y,z
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 3,
+        default_values_t = [0.0, 0.0, 0.0],
+        allow_negative_numbers = true,
+        requires = "orbit"
+    )]
+    pub pivot: Vec<f32>,
+    /// Enable the Khronos validation layer, logging its messages with the "vulkan" target
+    #[arg(long)]
+    pub validation: bool,
+    /// Only log messages of at least this level, overriding RUST_LOG [default: RUST_LOG, or
+    /// info]
+    #[arg(long)]
+    pub log_level: Option<LogLevel>,
+    /// Log as human readable text, or as one JSON object per line for log pipelines
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Write a chrome trace of the render loop's phases to this file, viewable in Perfetto or
+    /// chrome://tracing
+    #[arg(long)]
+    pub profile: Option<PathBuf>,
+    /// Log the dmatex id and the acquire and release timeline points of every submitted frame,
+    /// and the points the preview panel samples it at
+    #[arg(long)]
+    pub log_timeline: bool,
+    /// Render in R16G16B16A16_SFLOAT if the server supports it, tone mapping it for the window
+    #[arg(long)]
+    pub hdr: bool,
+    /// Multiply the image's colors by this before it reaches the window or recorder, costs an
+    /// extra shader pass
+    #[arg(long)]
+    pub exposure: Option<f32>,
+    /// Raise the image's colors to 1/gamma before it reaches the window or recorder, costs an
+    /// extra shader pass
+    #[arg(long)]
+    pub gamma: Option<f32>,
+    /// Keep the frames of the last this many seconds in memory, F9 saves them as a PNG sequence
+    /// in replay-<time>/. Sized for --max-fps, or 60 fps without it
+    #[arg(long, value_parser = parse_seconds, conflicts_with = "headless")]
+    pub replay_seconds: Option<Duration>,
+    /// Frames in the exposure bracket F10 captures, saved as bracket-<time>-<i>_ev<stops>.png
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(2..))]
+    pub bracket_count: u32,
+    /// Exposure difference between the bracket's frames in stops
+    #[arg(long, default_value_t = 1.0)]
+    pub bracket_stops: f32,
+    /// Model shown in front of the camera, as namespace:name
+    #[arg(long, default_value = "vk:panel")]
+    pub model: ModelResource,
+    /// Part of --model the rendered image is applied to
+    #[arg(long, default_value = "Panel")]
+    pub model_part: String,
+    /// Recreate the previews whenever the file of --model changes, keeping the old ones if the
+    /// new file fails to load. Only works for the models in this client's resources
+    #[arg(long, conflicts_with = "no_preview")]
+    pub watch_model: bool,
+    /// Don't show the model in the scene, e.g. when only recording or streaming
+    #[arg(long, conflicts_with_all = ["model", "model_part"])]
+    pub no_preview: bool,
+    /// Starting camera pose from `--config`, replacing the one saved by the server
+    #[arg(skip)]
+    pub camera: Option<CameraPose>,
+    /// Parameters from `--config` set on the preview panel's material once it's created
+    #[arg(skip)]
+    pub material: BTreeMap<String, MaterialValue>,
+}
+
+/// A namespaced resource like `vk:panel`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ModelResource {
+    pub namespace: String,
+    pub name: String,
+}
+impl ModelResource {
+    pub fn resource_id(&self) -> ResourceID {
+        ResourceID::new_namespaced(&self.namespace, &self.name)
+    }
+
+    /// The model's file if it's in this client's resources.
+    pub fn local_path(&self) -> Option<PathBuf> {
+        let dir = project_local_resources!("res").join(&self.namespace);
+        ["glb", "gltf"]
+            .into_iter()
+            .map(|extension| dir.join(format!("{}.{extension}", self.name)))
+            .find(|path| path.is_file())
+    }
+}
+impl FromStr for ModelResource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => Ok(Self {
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+            }),
+            _ => Err(format!("expected namespace:name, got {s:?}")),
+        }
+    }
+}
+impl TryFrom<String> for ModelResource {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+impl fmt::Display for ModelResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.name)
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<PhysicalSize<u32>, String> {
+    let parse = |value: &str| value.parse::<u32>().ok().filter(|value| *value > 0);
+    match s.split_once('x') {
+        Some((width, height)) => match (parse(width), parse(height)) {
+            (Some(width), Some(height)) => Ok(PhysicalSize::new(width, height)),
+            _ => Err(format!("expected a positive width and height, got {s:?}")),
+        },
+        None => Err(format!("expected WIDTHxHEIGHT, got {s:?}")),
+    }
+}
+
+/// A number with an `ms`, `s`, `m` or `h` suffix, plain numbers are seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = [("ms", 0.001), ("s", 1.0), ("m", 60.0), ("h", 3600.0)]
+        .into_iter()
+        .find_map(|(suffix, unit)| Some((s.strip_suffix(suffix)?, unit)))
+        .unwrap_or((s, 1.0));
+    match number.trim().parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(Duration::from_secs_f64(value * unit)),
+        _ => Err(format!("expected a positive duration like 10s, got {s:?}")),
+    }
+}
+
+fn parse_supersample(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(factor) if factor >= 1.0 && factor.is_finite() => Ok(factor),
+        _ => Err(format!("expected a factor of at least 1, got {s:?}")),
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("expected a positive number of seconds, got {s:?}")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentModeArg {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+impl From<PresentModeArg> for PresentMode {
+    fn from(value: PresentModeArg) -> Self {
+        match value {
+            PresentModeArg::Fifo => PresentMode::Fifo,
+            PresentModeArg::Mailbox => PresentMode::Mailbox,
+            PresentModeArg::Immediate => PresentMode::Immediate,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorSpaceArg {
+    Srgb,
+    ExtendedSrgbLinear,
+    Hdr10,
+}
+impl From<ColorSpaceArg> for ColorSpace {
+    fn from(value: ColorSpaceArg) -> Self {
+        match value {
+            ColorSpaceArg::Srgb => ColorSpace::SrgbNonLinear,
+            ColorSpaceArg::ExtendedSrgbLinear => ColorSpace::ExtendedSrgbLinear,
+            ColorSpaceArg::Hdr10 => ColorSpace::Hdr10St2084,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+impl From<LogLevel> for LevelFilter {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Trace => LevelFilter::TRACE,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Error => LevelFilter::ERROR,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SkipPolicy {
+    /// Skip them, the next frame renders the newest camera state. Keeps latency low for live
+    /// monitoring
+    Latest,
+    /// Render every frame, waiting for the GPU to catch up
+    Queue,
+}
+impl Args {
+    /// Parses the command line and merges in `--config` if given.
+    pub fn load() -> Result<Self, SetupError> {
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        if let Some(path) = &args.config {
+            Config::load(path)?.apply(&mut args, &matches);
+        }
+        if args.accumulate.is_some() {
+            args.once = true;
+        }
+        if args.once {
+            args.headless = true;
+            args.frames = Some(1);
+        }
+        Ok(args)
+    }
+
+    pub fn gpu_selector(&self) -> Option<GpuSelector> {
+        match (self.gpu, &self.gpu_name) {
+            (Some(index), _) => Some(GpuSelector::Index(index)),
+            (None, Some(name)) => Some(GpuSelector::Name(name.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// Acceptable present modes in order of preference, fifo is used if none are supported.
+    pub fn present_modes(&self) -> Vec<PresentMode> {
+        match self.present_mode {
+            Some(present_mode) => vec![present_mode.into()],
+            None if self.no_vsync => vec![PresentMode::Immediate, PresentMode::Mailbox],
+            None => vec![PresentMode::Fifo],
+        }
+    }
+
+    /// Frames the replay ring keeps for `--replay-seconds`, sized for `--max-fps` or
+    /// [`DEFAULT_REPLAY_FPS`] without a limit.
+    pub fn replay_frames(&self) -> Option<usize> {
+        let fps = self.max_fps.map_or(DEFAULT_REPLAY_FPS, |fps| fps as f32);
+        self.replay_seconds
+            .map(|seconds| ((seconds.as_secs_f32() * fps).ceil() as usize).max(1))
+    }
+
+    /// `--log-level` if given, otherwise the directives in `RUST_LOG` on top of info.
+    pub fn log_filter(&self) -> EnvFilter {
+        match self.log_level {
+            Some(level) => EnvFilter::default().add_directive(LevelFilter::from(level).into()),
+            None => EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        }
+    }
+
+    /// Sizes of the left and right eye with `--left-res` and `--right-res`.
+    pub fn eye_sizes(&self) -> Option<[PhysicalSize<u32>; 2]> {
+        Some([self.left_res?, self.right_res?])
+    }
+
+    /// Fixed size of each rendered view, independent of the window size.
+    pub fn render_size(&self) -> Option<PhysicalSize<u32>> {
+        Some(PhysicalSize::new(self.render_width?, self.render_height?))
+    }
+
+    pub fn pivot(&self) -> Vec3 {
+        Vec3::from_slice(&self.pivot)
+    }
+
+    pub fn clear_color(&self) -> Option<[f32; 4]> {
+        self.clear_color
+            .as_deref()
+            .map(|color| [color[0], color[1], color[2], color[3]])
+    }
+
+    pub fn look_at(&self) -> Option<Vec3> {
+        self.look_at.as_deref().map(Vec3::from_slice)
+    }
+
+    /// Applies `--pos` and `--rot` or `--look-at` to the starting camera.
+    pub fn apply_pose(&self, fly_camera: &mut FlyCamera) {
+        if let Some(pos) = &self.pos {
+            fly_camera.position = Vec3::from_slice(pos);
+        }
+        if let Some(rot) = &self.rot {
+            fly_camera.yaw = rot[0].to_radians();
+            fly_camera.pitch = rot[1].to_radians();
+            fly_camera.roll = rot[2].to_radians();
+        }
+        if let Some(target) = self.look_at()
+            && !fly_camera.look_at(target)
+        {
+            warn!(
+                ?target,
+                "--look-at is the camera's position, keeping its rotation"
+            );
+        }
+    }
+
+    /// Whether `--raw-out` writes to stdout, which leaves only stderr for logging.
+    pub fn raw_out_is_stdout(&self) -> bool {
+        self.raw_out.as_deref() == Some(Path::new("-"))
+    }
+
+    /// Whether headless frames are converted to NV12, either for --yuv or --stream.
+    pub fn nv12(&self) -> bool {
+        self.yuv || self.stream.is_some()
+    }
+
+    /// `None` unless `--exposure` or `--gamma` was passed, so the pass is skipped by default.
+    pub fn color_correction(&self) -> Result<Option<ColorCorrection>, String> {
+        if self.exposure.is_none() && self.gamma.is_none() {
+            return Ok(None);
+        }
+        let correction = ColorCorrection {
+            exposure: self.exposure.unwrap_or(1.0),
+            gamma: self.gamma.unwrap_or(1.0),
+        };
+        if correction.exposure.is_nan() || correction.exposure < 0.0 {
+            return Err(format!(
+                "--exposure must not be negative, got {}",
+                correction.exposure
+            ));
+        }
+        if correction.gamma.is_nan() || correction.gamma <= 0.0 {
+            return Err(format!(
+                "--gamma must be positive, got {}",
+                correction.gamma
+            ));
+        }
+        Ok(Some(correction))
+    }
+
+    pub fn view_layout(&self) -> ViewLayout {
+        if self.stereo {
+            ViewLayout::Stereo { ipd: self.ipd }
+        } else {
+            ViewLayout::Mono
+        }
+    }
+
+    /// `saved_fov` is used if `--fov` wasn't passed.
+    pub fn projection(&self, saved_fov: Option<f32>) -> Result<Projection, String> {
+        let fov = self.fov.or(saved_fov).unwrap_or(60.0);
+        if !(fov > 0.0 && fov < 180.0) {
+            return Err(format!(
+                "--fov must be between 0 and 180 degrees, got {fov}"
+            ));
+        }
+        if !(self.near > 0.0 && self.far > 0.0) {
+            return Err(format!(
+                "--near and --far must be positive, got near={} far={}",
+                self.near, self.far
+            ));
+        }
+        if self.near == self.far {
+            return Err(format!(
+                "--near and --far must differ, both are {}",
+                self.near
+            ));
+        }
+        if self.near > self.far && !self.reverse_z {
+            return Err(format!(
+                "--near ({}) is greater than --far ({}), which is only valid with --reverse-z",
+                self.near, self.far
+            ));
+        }
+        if self.pixel_aspect.is_nan() || self.pixel_aspect <= 0.0 {
+            return Err(format!(
+                "--pixel-aspect must be positive, got {}",
+                self.pixel_aspect
+            ));
+        }
+        if self.ortho && (self.ortho_size.is_nan() || self.ortho_size <= 0.0) {
+            return Err(format!(
+                "--ortho-size must be positive, got {}",
+                self.ortho_size
+            ));
+        }
+        Ok(Projection {
+            fov_y: fov.to_radians(),
+            near: self.near,
+            far: self.far,
+            ortho_height: self.ortho.then_some(self.ortho_size),
+            pixel_aspect: self.pixel_aspect,
+            lens_shift: [self.lens_shift[0], self.lens_shift[1]],
+        })
+    }
+}
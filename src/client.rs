@@ -0,0 +1,193 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+use glam::{Quat, Vec3};
+use stardust_xr_fusion::AsyncEventLoop;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use vulkano::{
+    VulkanLibrary,
+    command_buffer::allocator::StandardCommandBufferAllocator,
+    device::QueueFlags,
+    instance::{Instance, InstanceCreateInfo},
+    memory::allocator::StandardMemoryAllocator,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    connection::Connection,
+    error::{SetupContext, SetupError},
+    fly_camera::{FlyCamera, FlyCameras},
+    frame::{Renderer, create_cme_swapchain},
+    frame_channel::{FrameReceiver, frame_channel},
+    gpu::{create_device, select_physical_device, select_queue_family},
+    headless::{Destination, Frame, PngSink},
+    projection::Projection,
+    stardust::{StardustLoop, Target},
+    views::ViewLayout,
+};
+
+/// How a [`CameraClient`] renders, the library counterpart of the binary's arguments.
+#[derive(Debug, Clone)]
+pub struct CameraClientOptions {
+    /// Size of each view, multiple views are delivered side by side
+    pub size: PhysicalSize<u32>,
+    pub projection: Projection,
+    pub view_layout: ViewLayout,
+    /// Renders into the 16-bit float format if the server supports it, tone mapped when read
+    /// back
+    pub hdr: bool,
+    /// Whether to show a panel with the rendered image in the scene
+    pub preview: bool,
+    pub camera: FlyCamera,
+}
+impl Default for CameraClientOptions {
+    fn default() -> Self {
+        Self {
+            size: PhysicalSize::new(1280, 720),
+            projection: Projection {
+                fov_y: 60f32.to_radians(),
+                near: 300.0,
+                far: 0.003,
+                ortho_height: None,
+                pixel_aspect: 1.0,
+                lens_shift: [0.0; 2],
+            },
+            view_layout: ViewLayout::Mono,
+            hdr: false,
+            preview: true,
+            camera: FlyCamera::new(Vec3::new(0.0, 0.2, 0.2), -90f32.to_radians(), 0.0),
+        }
+    }
+}
+
+/// Renders the stardust scene headless and hands every frame to a callback instead of writing
+/// it out, for embedding the camera in other applications.
+pub struct CameraClient {
+    stardust_loop: StardustLoop,
+    async_loop: AsyncEventLoop,
+}
+impl CameraClient {
+    /// Connects to the server and sets up the device, nothing is rendered until
+    /// [`Self::start`]. `on_frame` is called from the render task, so it should return quickly.
+    pub async fn new(
+        options: CameraClientOptions,
+        on_frame: impl FnMut(Frame) + Send + 'static,
+    ) -> Result<Self, SetupError> {
+        let (connection, async_loop, _) = Connection::connect(options.hdr).await?;
+        let library = VulkanLibrary::new().vk_context("loading the vulkan library")?;
+        let instance = Instance::new(library, InstanceCreateInfo::default())
+            .vk_context("creating the instance")?;
+        let phys_dev = select_physical_device(&instance, &connection.render_dev, None)?;
+        let queue_families =
+            select_queue_family(&phys_dev, QueueFlags::TRANSFER, options.hdr, None, None)?;
+        let (dev, queue, _) = create_device(&phys_dev, queue_families, false, true)?;
+        let renderer = Renderer::new(
+            dev.clone(),
+            queue,
+            Arc::new(StandardCommandBufferAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+        );
+        let memalloc = Arc::new(StandardMemoryAllocator::new_default(dev.clone()));
+        let views = options.view_layout.count();
+        let cme_swapchain = create_cme_swapchain(
+            &connection,
+            &dev,
+            connection.cme_format,
+            options.size,
+            views,
+        )?;
+        let target = Target::Headless(PngSink::new(
+            cme_swapchain,
+            memalloc,
+            options.size,
+            views,
+            Destination::Callback(Box::new(on_frame)),
+            None,
+            connection.cme_format,
+            None,
+        )?);
+        let mut stardust_loop = StardustLoop::new(
+            renderer,
+            target,
+            instance,
+            connection,
+            options.projection,
+            FlyCameras::new(options.camera, 1),
+        );
+        stardust_loop.view_layout = options.view_layout;
+        stardust_loop.preview = options.preview;
+        stardust_loop.hdr = options.hdr;
+        Ok(Self {
+            stardust_loop,
+            async_loop,
+        })
+    }
+
+    /// Like [`Self::new`], but queues up to `capacity` frames for the returned receiver, dropping
+    /// the oldest one when the receiver falls behind.
+    pub async fn with_channel(
+        options: CameraClientOptions,
+        capacity: NonZeroUsize,
+    ) -> Result<(Self, FrameReceiver), SetupError> {
+        let (sender, receiver) = frame_channel(capacity);
+        let client = Self::new(options, move |frame| sender.send(frame)).await?;
+        Ok((client, receiver))
+    }
+
+    /// Moves the camera from outside, e.g. driven by other input.
+    pub fn camera_control(&self) -> CameraControl {
+        CameraControl {
+            fly_cameras: self.stardust_loop.fly_cameras.clone(),
+        }
+    }
+
+    /// Starts rendering on a new task, reconnecting whenever the server goes away until the
+    /// returned handle is stopped.
+    pub fn start(self) -> CameraClientHandle {
+        let shutdown = self.stardust_loop.shutdown.clone();
+        CameraClientHandle {
+            task: tokio::spawn(self.stardust_loop.run(self.async_loop)),
+            shutdown,
+        }
+    }
+}
+
+/// Sets the pose of a [`CameraClient`]'s camera. The whole pose is swapped at once and applied
+/// before the next frame is drawn, so no frame shows a half updated pose.
+#[derive(Clone)]
+pub struct CameraControl {
+    fly_cameras: Arc<Mutex<FlyCameras>>,
+}
+impl CameraControl {
+    pub fn set_pose(&self, position: Vec3, rotation: Quat) {
+        self.fly_cameras
+            .lock()
+            .unwrap()
+            .primary_mut()
+            .set_pose(position, rotation);
+    }
+
+    pub fn pose(&self) -> (Vec3, Quat) {
+        let fly_cameras = self.fly_cameras.lock().unwrap();
+        let camera = fly_cameras.primary();
+        (camera.position, camera.orientation())
+    }
+}
+
+/// A running [`CameraClient`].
+pub struct CameraClientHandle {
+    task: JoinHandle<Result<(), SetupError>>,
+    shutdown: CancellationToken,
+}
+impl CameraClientHandle {
+    /// Stops rendering and waits for the client to disconnect.
+    pub async fn stop(self) -> Result<(), SetupError> {
+        self.shutdown.cancel();
+        self.task.await.map_err(SetupError::TaskPanicked)?
+    }
+}
@@ -0,0 +1,248 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use clap::{ArgMatches, parser::ValueSource};
+use glam::Vec3;
+use serde::Deserialize;
+use stardust_xr_fusion::{drawable::MaterialParameter, values::color::rgba_linear};
+use tracing::warn;
+
+use crate::{
+    cli::{Args, ModelResource, PresentModeArg},
+    error::SetupError,
+    fly_camera::FlyCamera,
+    frame::Scaling,
+    grid::Grid,
+};
+
+/// Options loaded from `--config`, keys are named like the CLI flags. Missing keys keep the CLI
+/// defaults and flags passed on the command line override the file.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    fov: Option<f32>,
+    near: Option<f32>,
+    far: Option<f32>,
+    reverse_z: Option<bool>,
+    render_width: Option<u32>,
+    render_height: Option<u32>,
+    gpu: Option<usize>,
+    gpu_name: Option<String>,
+    stereo: Option<bool>,
+    ipd: Option<f32>,
+    present_mode: Option<PresentModeArg>,
+    max_fps: Option<u32>,
+    scaling: Option<Scaling>,
+    grid: Option<Grid>,
+    orbit: Option<bool>,
+    pivot: Option<[f32; 3]>,
+    validation: Option<bool>,
+    hdr: Option<bool>,
+    model: Option<ModelResource>,
+    model_part: Option<String>,
+    camera: Option<CameraPose>,
+    #[serde(default)]
+    material: BTreeMap<String, MaterialValue>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// A parameter set on the preview panel's material, from the `[material]` table. Vectors are 2
+/// or 3 numbers, colors 4 linear r,g,b,a numbers and textures a `namespace:name` resource.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "a bool, a number, 2 to 4 numbers or a namespace:name texture"
+)]
+pub enum MaterialValue {
+    Bool(bool),
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Color([f32; 4]),
+    Texture(ModelResource),
+}
+impl MaterialValue {
+    pub fn parameter(&self) -> MaterialParameter {
+        match self {
+            Self::Bool(value) => MaterialParameter::Bool(*value),
+            Self::Float(value) => MaterialParameter::Float(*value),
+            Self::Vec2(value) => MaterialParameter::Vec2((*value).into()),
+            Self::Vec3(value) => MaterialParameter::Vec3((*value).into()),
+            Self::Color([r, g, b, a]) => MaterialParameter::Color(rgba_linear!(*r, *g, *b, *a)),
+            Self::Texture(resource) => MaterialParameter::Texture(resource.resource_id()),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Bool(_) => "bool",
+            Self::Float(_) => "float",
+            Self::Vec2(_) => "vec2",
+            Self::Vec3(_) => "vec3",
+            Self::Color(_) => "color",
+            Self::Texture(_) => "texture",
+        }
+    }
+}
+
+/// Starting pose of the camera, used instead of the state saved by the server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CameraPose {
+    position: [f32; 3],
+    /// In degrees
+    #[serde(default)]
+    yaw: f32,
+    /// In degrees
+    #[serde(default)]
+    pitch: f32,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+impl CameraPose {
+    pub fn fly_camera(&self) -> FlyCamera {
+        FlyCamera::new(
+            Vec3::from(self.position),
+            self.yaw.to_radians(),
+            self.pitch.to_radians(),
+        )
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, SetupError> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| SetupError::ConfigRead(path.to_path_buf(), err))?;
+        let mut config: Self = toml::from_str(&text)
+            .map_err(|err| SetupError::ConfigParse(path.to_path_buf(), err))?;
+        let camera_unknown = config
+            .camera
+            .iter()
+            .flat_map(|camera| camera.unknown.keys().map(|key| format!("camera.{key}")));
+        for key in config.unknown.keys().cloned().chain(camera_unknown) {
+            warn!(path = %path.display(), key, "ignoring unknown config key");
+        }
+        // the server doesn't report what the panel's shader expects, only the parameters this
+        // client sets itself can be checked
+        config
+            .material
+            .retain(|name, value| match (name.as_str(), value) {
+                ("diffuse", _) => {
+                    warn!(
+                        path = %path.display(),
+                        "ignoring material.diffuse, the rendered image replaces it every frame"
+                    );
+                    false
+                }
+                ("unlit", MaterialValue::Bool(_)) => true,
+                ("unlit", value) => {
+                    warn!(
+                        path = %path.display(),
+                        expected = "bool",
+                        found = value.type_name(),
+                        "ignoring material.unlit of the wrong type"
+                    );
+                    false
+                }
+                _ => true,
+            });
+        Ok(config)
+    }
+
+    /// Moves the file's values into `args`, except for the ones passed on the command line.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        macro_rules! merge {
+            ($($field:ident),* $(,)?) => {$(
+                if let Some(value) = self.$field
+                    && !from_cli(stringify!($field))
+                {
+                    args.$field = value.into();
+                }
+            )*};
+        }
+        merge!(
+            fov,
+            near,
+            far,
+            reverse_z,
+            render_width,
+            render_height,
+            gpu,
+            gpu_name,
+            stereo,
+            ipd,
+            present_mode,
+            max_fps,
+            scaling,
+            grid,
+            orbit,
+            pivot,
+            validation,
+            hdr,
+            model,
+            model_part,
+        );
+        args.camera = self.camera;
+        args.material = self.material;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+
+    /// `args` parsed from `cli` with `config` merged in, like [`Args::load`] does.
+    fn merged(config: &str, cli: &[&str]) -> Args {
+        let matches = Args::command()
+            .try_get_matches_from(["stardust_camera_client"].iter().chain(cli))
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        toml::from_str::<Config>(config)
+            .unwrap()
+            .apply(&mut args, &matches);
+        args
+    }
+
+    #[test]
+    fn config_fills_in_unset_flags() {
+        let args = merged("fov = 75.0\nmax-fps = 30", &[]);
+        assert_eq!(args.fov, Some(75.0));
+        assert_eq!(args.max_fps, Some(30));
+    }
+
+    #[test]
+    fn config_replaces_defaults() {
+        let args = merged("near = 100.0\nmodel-part = \"Screen\"", &[]);
+        assert_eq!(args.near, 100.0);
+        assert_eq!(args.model_part, "Screen");
+        // untouched by the file
+        assert_eq!(args.far, 0.003);
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let args = merged(
+            "fov = 75.0\nnear = 100.0\nhdr = true",
+            &["--fov", "90", "--near", "200"],
+        );
+        assert_eq!(args.fov, Some(90.0));
+        assert_eq!(args.near, 200.0);
+        assert!(args.hdr);
+    }
+
+    #[test]
+    fn config_only_options_are_moved_over() {
+        let args = merged(
+            "[camera]\nposition = [1.0, 2.0, 3.0]\n\n[material]\nunlit = false",
+            &[],
+        );
+        assert!(args.camera.is_some());
+        assert!(matches!(
+            args.material.get("unlit"),
+            Some(MaterialValue::Bool(false))
+        ));
+    }
+}
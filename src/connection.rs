@@ -0,0 +1,89 @@
+use std::{collections::HashMap, sync::Arc};
+
+use stardust_xr_cme::{format::DmatexFormat, render_device::RenderDevice};
+use stardust_xr_fusion::{AsyncEventLoop, Client, ClientHandle, project_local_resources};
+use tracing::{info, warn};
+use vulkano::format::Format;
+
+use crate::{
+    error::{SetupContext, SetupError},
+    frame::{CME_FORMATS, HDR_CME_FORMAT},
+    state::SavedState,
+};
+
+/// Everything tied to a single connection to the stardust server, replaced on reconnect.
+#[derive(Clone)]
+pub struct Connection {
+    pub client: Arc<ClientHandle>,
+    pub render_dev: Arc<RenderDevice>,
+    pub formats: Arc<HashMap<Format, DmatexFormat>>,
+    /// Format of the CME swapchains, [`HDR_CME_FORMAT`] if requested and supported, otherwise
+    /// the first supported one of [`CME_FORMATS`]
+    pub cme_format: Format,
+}
+impl Connection {
+    /// Connects to the server, also returning the event loop that has to be kept alive for the
+    /// connection's lifetime and the state saved by a previous instance.
+    pub async fn connect(
+        hdr: bool,
+    ) -> Result<(Self, AsyncEventLoop, Option<SavedState>), SetupError> {
+        let client = Client::connect()
+            .await
+            .map_err(|err| SetupError::Connect(err.into()))?;
+        client
+            .setup_resources(&[&project_local_resources!("res")])
+            .map_err(|err| SetupError::Connect(err.into()))?;
+        let saved_state = client.get_state().data::<SavedState>();
+        if let Some(saved_state) = &saved_state {
+            info!(?saved_state, "found saved state");
+        }
+
+        let async_loop = client.async_event_loop();
+        let client = async_loop.client_handle.clone();
+        let render_dev = Arc::new(
+            RenderDevice::primary_server_device(&client)
+                .await
+                .map_err(|err| SetupError::RenderDevice(err.into()))?,
+        );
+        let formats = Arc::new(
+            DmatexFormat::enumerate(&client, &render_dev)
+                .await
+                .map_err(|err| SetupError::Interop {
+                    context: "enumerating dmatex formats",
+                    source: err.into(),
+                })?,
+        );
+        let cme_format = if hdr && formats.contains_key(&HDR_CME_FORMAT) {
+            HDR_CME_FORMAT
+        } else {
+            if hdr {
+                warn!(
+                    format = ?HDR_CME_FORMAT,
+                    "the server does not support the hdr format, falling back to 8-bit"
+                );
+            }
+            let format = CME_FORMATS
+                .into_iter()
+                .find(|format| formats.contains_key(format))
+                .ok_or(SetupError::NoUsableDmatexFormat(CME_FORMATS.to_vec()))?;
+            if format != CME_FORMATS[0] {
+                warn!(
+                    ?format,
+                    "the server does not support {:?}, falling back", CME_FORMATS[0]
+                );
+            }
+            format
+        };
+        info!(?cme_format);
+        Ok((
+            Self {
+                client,
+                render_dev,
+                formats,
+                cme_format,
+            },
+            async_loop,
+            saved_state,
+        ))
+    }
+}
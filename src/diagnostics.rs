@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use tracing::error;
+use vulkano::{device::physical::PhysicalDevice, instance::Instance, swapchain::Surface};
+use winit::{
+    application::ApplicationHandler,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop},
+    platform::run_on_demand::EventLoopExtRunOnDemand,
+    window::{Window, WindowId},
+};
+
+use crate::{
+    connection::Connection,
+    error::{SetupContext, SetupError},
+};
+
+/// Prints the dmatex formats the server supports and what a default window's surface supports
+/// on `phys_dev`, for pasting into bug reports.
+pub fn list_formats(
+    connection: &Connection,
+    instance: Arc<Instance>,
+    phys_dev: Arc<PhysicalDevice>,
+    event_loop: &mut EventLoop<()>,
+) -> Result<(), SetupError> {
+    println!("gpu: {}", phys_dev.properties().device_name);
+    let mut formats: Vec<_> = connection.formats.iter().collect();
+    formats.sort_by_key(|(format, _)| format!("{format:?}"));
+    println!("dmatex formats supported by the server:");
+    for (format, dmatex_format) in formats {
+        println!("  {format:?}: {dmatex_format:?}");
+    }
+
+    let mut lister = SurfaceLister {
+        instance,
+        phys_dev,
+        result: None,
+    };
+    event_loop
+        .run_app_on_demand(&mut lister)
+        .vk_context("running the event loop")?;
+    lister.result.unwrap_or(Ok(()))
+}
+
+/// Opens an invisible window once the event loop is running, since surfaces need one.
+struct SurfaceLister {
+    instance: Arc<Instance>,
+    phys_dev: Arc<PhysicalDevice>,
+    result: Option<Result<(), SetupError>>,
+}
+impl SurfaceLister {
+    fn list(&self, event_loop: &ActiveEventLoop) -> Result<(), SetupError> {
+        let window = Arc::new(
+            event_loop
+                .create_window(Window::default_attributes().with_visible(false))
+                .vk_context("creating the window")?,
+        );
+        let surface = Surface::from_window(self.instance.clone(), window)
+            .vk_context("creating the window surface")?;
+        println!("surface formats:");
+        for (format, color_space) in self
+            .phys_dev
+            .surface_formats(&surface, Default::default())
+            .vk_context("querying surface formats")?
+        {
+            println!("  {format:?} {color_space:?}");
+        }
+        println!("surface present modes:");
+        for present_mode in self
+            .phys_dev
+            .surface_present_modes(&surface, Default::default())
+            .vk_context("querying surface present modes")?
+        {
+            println!("  {present_mode:?}");
+        }
+        Ok(())
+    }
+}
+impl ApplicationHandler for SurfaceLister {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.result.is_none() {
+            let result = self.list(event_loop);
+            if let Err(err) = &result {
+                error!("{err}");
+            }
+            self.result = Some(result);
+        }
+        event_loop.exit();
+    }
+
+    fn window_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _window_id: WindowId,
+        _event: WindowEvent,
+    ) {
+    }
+}
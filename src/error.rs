@@ -0,0 +1,184 @@
+use std::{error::Error, io, path::PathBuf};
+
+use thiserror::Error;
+use vulkano::{
+    Validated, ValidationError, VulkanError, buffer::HostAccessError, device::QueueFlags,
+    format::Format,
+};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum SetupError {
+    #[error("failed to connect to the stardust server: {0}")]
+    Connect(#[source] BoxError),
+    #[error("failed to get the server's render device: {0}")]
+    RenderDevice(#[source] BoxError),
+    /// Sharing Dmatex images with the server failed, see [`SetupError::is_interop`].
+    #[error("dmatex interop failed while {context}: {source}")]
+    Interop {
+        context: &'static str,
+        #[source]
+        source: BoxError,
+    },
+    #[error("no vulkan device matches the server's render device")]
+    NoMatchingPhysicalDevice,
+    #[error("no gpu matches the selection, available gpus: {}", .available.join(", "))]
+    NoSuchGpu { available: Vec<String> },
+    #[error("no such monitor, available monitors: {}", .available.join(", "))]
+    NoSuchMonitor { available: Vec<String> },
+    #[error(
+        "gpu {requested} can't share dmatex images with the server, compatible gpus: {}",
+        .compatible.join(", ")
+    )]
+    IncompatibleGpu {
+        requested: String,
+        compatible: Vec<String>,
+    },
+    #[error("no queue family supports {0:?}")]
+    NoUsableQueueFamily(QueueFlags),
+    #[error("no queue family can present to the window")]
+    NoPresentQueueFamily,
+    #[error("queue family {0} can't be used: {1}")]
+    UnusableQueueFamily(u32, String),
+    #[error("the window surface supports no usable format, available formats: {0:?}")]
+    NoUsableSurfaceFormat(Vec<Format>),
+    #[error("the server does not support the dmatex format {0:?}")]
+    DmatexFormatUnavailable(Format),
+    #[error("the server supports none of the dmatex formats {0:?}")]
+    NoUsableDmatexFormat(Vec<Format>),
+    #[error("failed to load the model {model}: {source}")]
+    Model {
+        model: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("the model {model} has no part named {part:?}: {source}")]
+    NoSuchModelPart {
+        model: String,
+        part: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("the model {0} isn't one of this client's own resources, so it can't be watched")]
+    ModelNotLocal(String),
+    #[error("failed to watch the model {}: {1}", .0.display())]
+    WatchModel(PathBuf, #[source] notify::Error),
+    #[error("failed to read the config file {}: {1}", .0.display())]
+    ConfigRead(PathBuf, #[source] io::Error),
+    #[error("invalid config file {}: {1}", .0.display())]
+    ConfigParse(PathBuf, #[source] toml::de::Error),
+    #[error("failed to load the camera path {}: {1}", .0.display())]
+    CameraPath(PathBuf, #[source] BoxError),
+    #[error("failed to create the output directory {}: {1}", .0.display())]
+    OutputDir(PathBuf, #[source] io::Error),
+    #[error("invalid --out pattern {}: {1}", .0.display())]
+    OutPattern(PathBuf, String),
+    #[error("failed to open the raw output {}: {1}", .0.display())]
+    RawOut(PathBuf, #[source] io::Error),
+    #[error("failed to bind the export socket {}: {1}", .0.display())]
+    ExportSocket(PathBuf, #[source] io::Error),
+    #[error("failed to serve the status on port {0}: {1}")]
+    StatusServer(u16, #[source] io::Error),
+    #[error("expected a stream url like udp://host:port, got {0:?}")]
+    StreamUrl(String),
+    #[error("failed to start ffmpeg for streaming: {0}")]
+    Encoder(#[source] io::Error),
+    #[error("lost the gpu device or the window surface")]
+    DeviceLost,
+    #[error("failed to create the camera: {0}")]
+    Camera(#[source] BoxError),
+    #[error("failed to render a frame: {0}")]
+    Frame(#[source] FrameError),
+    /// An argument was valid on its own but not in combination with the server or the others,
+    /// the binary reports it like clap's own errors.
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("the rendering task panicked: {0}")]
+    TaskPanicked(#[source] tokio::task::JoinError),
+    #[error("vulkan initialization failed while {context}: {source}")]
+    VulkanInit {
+        context: &'static str,
+        #[source]
+        source: BoxError,
+    },
+}
+
+impl SetupError {
+    /// Whether sharing Dmatex images with the server failed, as opposed to the connection or
+    /// the local setup.
+    pub fn is_interop(&self) -> bool {
+        matches!(
+            self,
+            SetupError::RenderDevice(_)
+                | SetupError::NoMatchingPhysicalDevice
+                | SetupError::DmatexFormatUnavailable(_)
+                | SetupError::NoUsableDmatexFormat(_)
+                | SetupError::Interop { .. }
+        )
+    }
+}
+
+/// Why a single frame failed. Only [`FrameError::DeviceLost`] is fatal, the frame is skipped
+/// otherwise.
+#[derive(Debug, Error)]
+pub enum FrameError {
+    #[error("lost the gpu device")]
+    DeviceLost,
+    #[error("vulkan failed while {context}: {source}")]
+    Vulkan {
+        context: &'static str,
+        #[source]
+        source: Validated<VulkanError>,
+    },
+    #[error("failed to recreate the CME swapchains: {0}")]
+    Swapchain(#[source] Box<SetupError>),
+    #[error("failed to map a host visible buffer: {0}")]
+    HostAccess(#[source] HostAccessError),
+}
+impl From<FrameError> for SetupError {
+    fn from(err: FrameError) -> Self {
+        match err {
+            FrameError::DeviceLost => SetupError::DeviceLost,
+            err => SetupError::Frame(err),
+        }
+    }
+}
+
+pub trait FrameContext<T> {
+    /// Wraps the error in [`FrameError::Vulkan`], or [`FrameError::DeviceLost`] if that's what it
+    /// is. `context` should read like "submitting the frame".
+    fn frame_context(self, context: &'static str) -> Result<T, FrameError>;
+}
+impl<T> FrameContext<T> for Result<T, Validated<VulkanError>> {
+    fn frame_context(self, context: &'static str) -> Result<T, FrameError> {
+        self.map_err(|err| match err {
+            Validated::Error(VulkanError::DeviceLost) => FrameError::DeviceLost,
+            source => FrameError::Vulkan { context, source },
+        })
+    }
+}
+impl<T> FrameContext<T> for Result<T, VulkanError> {
+    fn frame_context(self, context: &'static str) -> Result<T, FrameError> {
+        self.map_err(Validated::Error).frame_context(context)
+    }
+}
+impl<T> FrameContext<T> for Result<T, Box<ValidationError>> {
+    fn frame_context(self, context: &'static str) -> Result<T, FrameError> {
+        self.map_err(Validated::ValidationError)
+            .frame_context(context)
+    }
+}
+
+pub trait SetupContext<T> {
+    /// Wraps the error in [`SetupError::VulkanInit`], `context` should read like "creating the instance".
+    fn vk_context(self, context: &'static str) -> Result<T, SetupError>;
+}
+impl<T, E: Into<BoxError>> SetupContext<T> for Result<T, E> {
+    fn vk_context(self, context: &'static str) -> Result<T, SetupError> {
+        self.map_err(|err| SetupError::VulkanInit {
+            context,
+            source: err.into(),
+        })
+    }
+}
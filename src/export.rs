@@ -0,0 +1,97 @@
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+use stardust_xr_fusion::drawable::DmatexSubmitInfo;
+use tracing::{info, warn};
+use vulkano::format::Format;
+
+use crate::error::SetupError;
+
+/// Publishes every frame the server received to local processes over a Unix socket, one line
+/// per frame: `cell dmatex_id release_point width height views format`.
+///
+/// No file descriptors are passed. `dmatex_id` names the Dmatex this client registered with the
+/// stardust server, which owns its dmabuf and timeline syncobj. A consumer samples it by being a
+/// client of the same server and setting a `MaterialParameter::Dmatex` with that `dmatex_id` and
+/// `release_point` as both its acquire and release point, the way the preview panel does, see
+/// `panel_submit_info`. The server then waits for the draw before sampling.
+pub struct ExportSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<UnixStream>,
+}
+impl ExportSocket {
+    /// Replaces a stale socket left at `path` by a previous run.
+    pub fn bind(path: PathBuf) -> Result<Self, SetupError> {
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(SetupError::ExportSocket(path, err)),
+        }
+        let listener = UnixListener::bind(&path)
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            })
+            .map_err(|err| SetupError::ExportSocket(path.clone(), err))?;
+        info!(path = %path.display(), "exporting frames");
+        Ok(Self {
+            listener,
+            path,
+            clients: Vec::new(),
+        })
+    }
+
+    /// Accepts new clients and sends them the frame. Clients that can't keep up are dropped
+    /// instead of stalling the loop.
+    pub fn publish(
+        &mut self,
+        cell: usize,
+        submit_info: &DmatexSubmitInfo,
+        extent: [u32; 3],
+        views: u32,
+        format: Format,
+    ) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => {
+                        info!("export client connected");
+                        self.clients.push(stream);
+                    }
+                    Err(err) => warn!("failed to set up an export client: {err}"),
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("failed to accept an export client: {err}");
+                    break;
+                }
+            }
+        }
+        if self.clients.is_empty() {
+            return;
+        }
+        let [width, height, _] = extent;
+        let line = format!(
+            "{cell} {} {} {width} {height} {views} {format:?}\n",
+            submit_info.dmatex_id, submit_info.release_point
+        );
+        self.clients
+            .retain_mut(|client| match client.write_all(line.as_bytes()) {
+                Ok(()) => true,
+                Err(err) => {
+                    info!("export client disconnected: {err}");
+                    false
+                }
+            });
+    }
+}
+impl Drop for ExportSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,259 @@
+use std::f32::consts::TAU;
+
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3};
+use stardust_xr_fusion::spatial::Transform;
+use winit::keyboard::KeyCode;
+
+use crate::gamepad::GamepadInput;
+
+const MOVE_SPEED: f32 = 0.5;
+const FAST_MULTIPLIER: f32 = 4.0;
+const LOOK_SENSITIVITY: f32 = 0.002;
+/// Radians per second at full right stick deflection
+const GAMEPAD_LOOK_SPEED: f32 = 2.5;
+/// Time constant in seconds of `--smooth-camera`, after it the velocity and the mouse look are
+/// about two thirds of the way to the input
+const SMOOTHING: f32 = 0.08;
+/// Below this speed in meters per second a smoothed camera stops
+const MIN_SPEED: f32 = 0.001;
+
+/// Flythrough camera state, fed by winit input and integrated once per stardust frame.
+#[derive(Debug, Clone)]
+pub struct FlyCamera {
+    pub position: Vec3,
+    /// Rotation around the Y axis in radians
+    pub yaw: f32,
+    /// Rotation around the X axis in radians, clamped to avoid flipping over
+    pub pitch: f32,
+    /// Rotation around the Z axis in radians, only set from the command line
+    pub roll: f32,
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    fast: bool,
+    looking: bool,
+    /// Eases movement and mouse look in and out over [`SMOOTHING`] with `--smooth-camera`
+    pub smooth: bool,
+    /// World space velocity, follows the held keys
+    velocity: Vec3,
+    /// Mouse look as `[yaw, pitch]` radians not applied yet while smoothing
+    pending_look: Vec2,
+}
+impl FlyCamera {
+    pub fn new(position: Vec3, yaw: f32, pitch: f32) -> Self {
+        Self {
+            position,
+            yaw,
+            pitch,
+            roll: 0.0,
+            forward: false,
+            back: false,
+            left: false,
+            right: false,
+            up: false,
+            down: false,
+            fast: false,
+            looking: false,
+            smooth: false,
+            velocity: Vec3::ZERO,
+            pending_look: Vec2::ZERO,
+        }
+    }
+
+    pub fn orientation(&self) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, self.roll)
+    }
+
+    /// Moves the camera to `position` looking along `rotation`, for poses set from outside.
+    pub fn set_pose(&mut self, position: Vec3, rotation: Quat) {
+        let (yaw, pitch, roll) = rotation.to_euler(EulerRot::YXZ);
+        self.position = position;
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.roll = roll;
+        self.stop();
+    }
+
+    /// Drops the smoothed motion still to come, so a pose set from elsewhere stays put.
+    fn stop(&mut self) {
+        self.velocity = Vec3::ZERO;
+        self.pending_look = Vec2::ZERO;
+    }
+
+    /// The camera's pose relative to the client root, the inverse of its view matrix.
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.orientation(), self.position)
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation_rotation(self.position, self.orientation())
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode, pressed: bool) {
+        let slot = match key {
+            KeyCode::KeyW => &mut self.forward,
+            KeyCode::KeyS => &mut self.back,
+            KeyCode::KeyA => &mut self.left,
+            KeyCode::KeyD => &mut self.right,
+            KeyCode::KeyE => &mut self.up,
+            KeyCode::KeyQ => &mut self.down,
+            KeyCode::ShiftLeft | KeyCode::ShiftRight => &mut self.fast,
+            _ => return,
+        };
+        *slot = pressed;
+    }
+
+    /// Releases all held keys and buttons, e.g. when switching to another camera mode.
+    pub fn reset_input(&mut self) {
+        *self = Self {
+            roll: self.roll,
+            smooth: self.smooth,
+            ..Self::new(self.position, self.yaw, self.pitch)
+        };
+    }
+
+    /// Turns the camera to face `target`, level and with pitch clamped like mouse look. Returns
+    /// false and leaves the camera alone if `target` is where the camera is.
+    pub fn look_at(&mut self, target: Vec3) -> bool {
+        let Some(direction) = (target - self.position).try_normalize() else {
+            return false;
+        };
+        // the camera looks along -Z
+        self.yaw = (-direction.x).atan2(-direction.z);
+        self.pitch = direction
+            .y
+            .asin()
+            .clamp(-89f32.to_radians(), 89f32.to_radians());
+        self.roll = 0.0;
+        self.stop();
+        true
+    }
+
+    /// Mouse look is only active while the right mouse button is held.
+    pub fn set_looking(&mut self, looking: bool) {
+        self.looking = looking;
+    }
+
+    /// Turns the camera right away, or once the next frames are integrated while smoothing.
+    pub fn handle_mouse_motion(&mut self, (dx, dy): (f64, f64)) {
+        if !self.looking {
+            return;
+        }
+        let look = -Vec2::new(dx as f32, dy as f32) * LOOK_SENSITIVITY;
+        if self.smooth {
+            self.pending_look += look;
+        } else {
+            self.turn(look);
+        }
+    }
+
+    fn turn(&mut self, look: Vec2) {
+        self.yaw += look.x;
+        self.pitch = (self.pitch + look.y).clamp(-89f32.to_radians(), 89f32.to_radians());
+    }
+
+    /// Moves the camera according to the held keys, `delta` is in seconds. While smoothing the
+    /// velocity and the mouse look approach the input over the frame's delta, so the motion
+    /// doesn't depend on when the input events arrived.
+    pub fn update(&mut self, delta: f32) {
+        let axis = |pos: bool, neg: bool| pos as i32 as f32 - neg as i32 as f32;
+        let local = Vec3::new(
+            axis(self.right, self.left),
+            axis(self.up, self.down),
+            axis(self.back, self.forward),
+        );
+        let speed = if self.fast {
+            MOVE_SPEED * FAST_MULTIPLIER
+        } else {
+            MOVE_SPEED
+        };
+        let target = self.orientation() * local.normalize_or_zero() * speed;
+        if self.smooth {
+            let blend = 1.0 - (-delta / SMOOTHING).exp();
+            let look = self.pending_look * blend;
+            self.pending_look -= look;
+            self.turn(look);
+            self.velocity = self.velocity.lerp(target, blend);
+            if local == Vec3::ZERO && self.velocity.length() < MIN_SPEED {
+                self.velocity = Vec3::ZERO;
+            }
+        } else {
+            self.velocity = target;
+        }
+        self.position += self.velocity * delta;
+    }
+
+    /// Moves and turns the camera by the gamepad's sticks, on top of the keyboard and mouse.
+    pub fn apply_gamepad(&mut self, input: &GamepadInput, delta: f32) {
+        self.yaw -= input.look.x * GAMEPAD_LOOK_SPEED * delta;
+        self.pitch = (self.pitch + input.look.y * GAMEPAD_LOOK_SPEED * delta)
+            .clamp(-89f32.to_radians(), 89f32.to_radians());
+        let local = Vec3::new(input.movement.x, 0.0, -input.movement.y);
+        let speed = MOVE_SPEED * (1.0 + (FAST_MULTIPLIER - 1.0) * input.boost);
+        self.position += self.orientation() * local * speed * delta;
+    }
+}
+
+/// One fly camera per grid cell, window input only moves the primary one.
+#[derive(Debug, Clone)]
+pub struct FlyCameras {
+    pub cameras: Vec<FlyCamera>,
+    pub primary: usize,
+    /// Startup poses restored by [`Self::reset_primary`]
+    initial: Vec<FlyCamera>,
+}
+impl FlyCameras {
+    /// Starts with `count` cameras at `first`'s position, each turned further around the Y axis
+    /// so the cells show different directions.
+    pub fn new(first: FlyCamera, count: usize) -> Self {
+        let cameras = (0..count)
+            .map(|i| {
+                let yaw = first.yaw + TAU * i as f32 / count as f32;
+                FlyCamera {
+                    roll: first.roll,
+                    smooth: first.smooth,
+                    ..FlyCamera::new(first.position, yaw, first.pitch)
+                }
+            })
+            .collect::<Vec<_>>();
+        Self {
+            initial: cameras.clone(),
+            cameras,
+            primary: 0,
+        }
+    }
+
+    pub fn primary(&self) -> &FlyCamera {
+        &self.cameras[self.primary]
+    }
+
+    pub fn primary_mut(&mut self) -> &mut FlyCamera {
+        &mut self.cameras[self.primary]
+    }
+
+    /// Hands input to the next camera, releasing everything held on the current one.
+    pub fn cycle_primary(&mut self) {
+        self.primary_mut().reset_input();
+        self.primary = (self.primary + 1) % self.cameras.len();
+    }
+
+    /// Moves the primary camera back to its startup pose, keeping held keys.
+    pub fn reset_primary(&mut self) {
+        let initial = &self.initial[self.primary];
+        let camera = &mut self.cameras[self.primary];
+        camera.position = initial.position;
+        camera.yaw = initial.yaw;
+        camera.pitch = initial.pitch;
+        camera.roll = initial.roll;
+        camera.stop();
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        for camera in &mut self.cameras {
+            camera.update(delta);
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use stardust_xr_cme::swapchain::Swapchain;
+use stardust_xr_fusion::drawable::{DmatexSize, DmatexSubmitInfo};
+use tracing::{trace_span, warn};
+use vulkano::{
+    command_buffer::{
+        AutoCommandBufferBuilder, BlitImageInfo, CommandBufferSubmitInfo, CommandBufferUsage,
+        ImageBlit, PrimaryAutoCommandBuffer, SemaphoreSubmitInfo, SubmitInfo,
+        allocator::StandardCommandBufferAllocator,
+    },
+    device::{Device, Queue, QueueGuard},
+    format::Format,
+    image::{Image, ImageSubresourceLayers, ImageUsage, sampler::Filter},
+    sync::fence::{Fence, FenceCreateInfo},
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    connection::Connection,
+    error::{FrameContext, FrameError, SetupError},
+};
+
+/// The 8-bit formats CME swapchains can be created with, the first one the server supports is
+/// used. Blits convert the others, the readback has to reorder BGRA and encode UNORM itself.
+pub const CME_FORMATS: [Format; 4] = [
+    Format::R8G8B8A8_SRGB,
+    Format::B8G8R8A8_SRGB,
+    Format::R8G8B8A8_UNORM,
+    Format::B8G8R8A8_UNORM,
+];
+/// Used instead of [`CME_FORMATS`] with `--hdr` if the server supports it, sinks have to tone
+/// map it down themselves.
+pub const HDR_CME_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// Somewhere the rendered CME image ends up after the server is done with it.
+pub trait FrameSink {
+    fn cme_swapchain(&mut self) -> &mut Swapchain;
+    /// Prepares the sink for a new frame, returning `false` skips the frame.
+    fn acquire(&mut self) -> bool;
+    /// Records the commands consuming `image` into the frame's command buffer.
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<Image>,
+    ) -> Result<(), FrameError>;
+    /// Records a frame that has no CME image, e.g. a status message while disconnected.
+    fn record_status(
+        &mut self,
+        _builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), FrameError> {
+        Ok(())
+    }
+    fn wait_semaphores(&self) -> Vec<SemaphoreSubmitInfo> {
+        Vec::new()
+    }
+    fn signal_semaphores(&self) -> Vec<SemaphoreSubmitInfo> {
+        Vec::new()
+    }
+    /// Runs on the queue right after the frame was submitted, e.g. to present.
+    fn after_submit(&mut self, _queue: &mut QueueGuard<'_>) {}
+    /// Whether the frame has to complete before [`Renderer::render`] returns, for sinks that
+    /// read back the image. Otherwise it's only waited for before the next frame starts.
+    fn wait_for_completion(&self) -> bool {
+        true
+    }
+    /// Runs once all recorded commands have completed, only called if
+    /// [`Self::wait_for_completion`].
+    fn complete(&mut self) {}
+    /// Runs instead of the rest of the frame when recording or submitting it failed, to give up
+    /// whatever [`Self::acquire`] took.
+    fn discard(&mut self) {}
+}
+
+/// A submitted frame whose resources have to stay alive until `fence` signals.
+struct InFlight {
+    fence: Arc<Fence>,
+    _cmd_buff: Arc<PrimaryAutoCommandBuffer>,
+}
+
+/// Where the time of one [`Renderer::render`] went.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderTimings {
+    /// Waiting for the sink and the next CME image
+    pub prepare: Duration,
+    /// Recording and submitting the commands
+    pub submit: Duration,
+    /// From the submit until the GPU reached the release point
+    pub gpu: Duration,
+}
+
+pub struct Renderer {
+    pub dev: Arc<Device>,
+    pub queue: Arc<Queue>,
+    pub cballoc: Arc<StandardCommandBufferAllocator>,
+    in_flight: Option<InFlight>,
+    /// While set every render is measured and appended, which waits for the GPU after each
+    /// submit so only use it for benchmarks
+    pub timings: Option<Vec<RenderTimings>>,
+}
+impl Renderer {
+    pub fn new(
+        dev: Arc<Device>,
+        queue: Arc<Queue>,
+        cballoc: Arc<StandardCommandBufferAllocator>,
+    ) -> Self {
+        Self {
+            dev,
+            queue,
+            cballoc,
+            in_flight: None,
+            timings: None,
+        }
+    }
+
+    /// Blocks until the last submitted frame completed, so nothing references its resources.
+    pub fn wait_in_flight(&mut self) -> Result<(), FrameError> {
+        if let Some(in_flight) = self.in_flight.take() {
+            let _span = trace_span!("wait_in_flight").entered();
+            in_flight
+                .fence
+                .wait(None)
+                .frame_context("waiting for the last frame")?;
+        }
+        Ok(())
+    }
+
+    /// Whether the last submitted frame is still running, rendering now would block in
+    /// [`Self::wait_in_flight`].
+    pub fn is_busy(&self) -> bool {
+        self.in_flight.as_ref().is_some_and(|in_flight| {
+            // an error shows up again when the frame is waited for
+            !in_flight.fence.is_signaled().unwrap_or(true)
+        })
+    }
+
+    /// Hands the next CME image to `sink` and submits it, returning the submit info for the
+    /// camera together with the image extent. `None` if the sink skipped the frame, on an error
+    /// the sink [`FrameSink::discard`]s it.
+    pub fn render(
+        &mut self,
+        sink: &mut impl FrameSink,
+    ) -> Result<Option<(DmatexSubmitInfo, [u32; 3])>, FrameError> {
+        self.wait_in_flight()?;
+        let start = Instant::now();
+        if !trace_span!("acquire").in_scope(|| sink.acquire()) {
+            return Ok(None);
+        }
+        let rendered = self.submit(sink, start);
+        if rendered.is_err() {
+            sink.discard();
+        }
+        rendered.map(Some)
+    }
+
+    fn submit(
+        &mut self,
+        sink: &mut impl FrameSink,
+        start: Instant,
+    ) -> Result<(DmatexSubmitInfo, [u32; 3]), FrameError> {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.cballoc.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .frame_context("allocating the command buffer")?;
+        let cme_info =
+            trace_span!("prepare_image").in_scope(|| sink.cme_swapchain().prepare_next_image());
+        let prepared = Instant::now();
+        let record_span = trace_span!("record").entered();
+        let recorded = sink
+            .record(&mut builder, cme_info.image())
+            .and_then(|()| builder.build().frame_context("building the command buffer"));
+        drop(record_span);
+        let cmd_buff = match recorded {
+            Ok(cmd_buff) => cmd_buff,
+            Err(err) => {
+                // the prepared slot still has to move from its wait to its release point, or
+                // the swapchain waits on it forever, the server just never gets to draw it
+                cme_info.submit(&self.dev, &self.queue, |wait, mut queue, release| unsafe {
+                    if let Err(err) = queue
+                        .submit(
+                            &[SubmitInfo {
+                                wait_semaphores: vec![SemaphoreSubmitInfo::new(wait)],
+                                signal_semaphores: vec![SemaphoreSubmitInfo::new(release)],
+                                ..Default::default()
+                            }],
+                            None,
+                        )
+                        .frame_context("releasing the unrecorded image")
+                    {
+                        warn!("{err}");
+                    }
+                });
+                return Err(err);
+            }
+        };
+        let extent = cme_info.image().extent();
+        let wait_for_completion = sink.wait_for_completion();
+        let fence = Arc::new(
+            Fence::new(self.dev.clone(), FenceCreateInfo::default())
+                .frame_context("creating the frame fence")?,
+        );
+        let mut submitted = prepared;
+        let mut result = Ok(());
+        let submit_span = trace_span!("submit").entered();
+        let submit_info =
+            cme_info.submit(&self.dev, &self.queue, |wait, mut queue, release| unsafe {
+                let mut wait_semaphores = vec![SemaphoreSubmitInfo::new(wait)];
+                wait_semaphores.extend(sink.wait_semaphores());
+                let mut signal_semaphores = vec![SemaphoreSubmitInfo::new(release)];
+                signal_semaphores.extend(sink.signal_semaphores());
+                result = queue
+                    .submit(
+                        &[SubmitInfo {
+                            wait_semaphores,
+                            command_buffers: vec![CommandBufferSubmitInfo::new(cmd_buff.clone())],
+                            signal_semaphores,
+                            ..Default::default()
+                        }],
+                        Some(&fence),
+                    )
+                    .frame_context("submitting the frame");
+                if result.is_err() {
+                    return;
+                }
+                submitted = Instant::now();
+                trace_span!("present").in_scope(|| sink.after_submit(&mut queue));
+            });
+        drop(submit_span);
+        // the release point is never reached if the submit failed, so the server must not get it
+        result?;
+        // only this frame's fence, the queue may already be busy with the present
+        if wait_for_completion || self.timings.is_some() {
+            let _span = trace_span!("wait_for_completion").entered();
+            fence.wait(None).frame_context("waiting for the frame")?;
+        }
+        if let Some(timings) = &mut self.timings {
+            timings.push(RenderTimings {
+                prepare: prepared - start,
+                submit: submitted - prepared,
+                gpu: submitted.elapsed(),
+            });
+        }
+        if wait_for_completion {
+            trace_span!("complete").in_scope(|| sink.complete());
+        } else {
+            self.in_flight = Some(InFlight {
+                fence,
+                _cmd_buff: cmd_buff,
+            });
+        }
+        Ok((submit_info, extent))
+    }
+
+    /// Like [`Self::render`] but without a CME image or the server, for showing a status while
+    /// disconnected.
+    pub fn render_status(&mut self, sink: &mut impl FrameSink) -> Result<(), FrameError> {
+        self.wait_in_flight()?;
+        if !sink.acquire() {
+            return Ok(());
+        }
+        let rendered = self.submit_status(sink);
+        if rendered.is_err() {
+            sink.discard();
+        }
+        rendered
+    }
+
+    fn submit_status(&mut self, sink: &mut impl FrameSink) -> Result<(), FrameError> {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.cballoc.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .frame_context("allocating the command buffer")?;
+        sink.record_status(&mut builder)?;
+        let cmd_buff = builder
+            .build()
+            .frame_context("building the command buffer")?;
+        let fence = Arc::new(
+            Fence::new(self.dev.clone(), FenceCreateInfo::default())
+                .frame_context("creating the status fence")?,
+        );
+        self.queue.with(|mut queue| unsafe {
+            queue
+                .submit(
+                    &[SubmitInfo {
+                        wait_semaphores: sink.wait_semaphores(),
+                        command_buffers: vec![CommandBufferSubmitInfo::new(cmd_buff.clone())],
+                        signal_semaphores: sink.signal_semaphores(),
+                        ..Default::default()
+                    }],
+                    Some(&fence),
+                )
+                .frame_context("submitting the status")?;
+            sink.after_submit(&mut queue);
+            Ok::<_, FrameError>(())
+        })?;
+        self.in_flight = Some(InFlight {
+            fence,
+            _cmd_buff: cmd_buff,
+        });
+        Ok(())
+    }
+}
+
+/// Size of a single view when `size` is split horizontally into `views` views.
+pub fn view_size(size: PhysicalSize<u32>, views: u32) -> PhysicalSize<u32> {
+    PhysicalSize::new(size.width / views, size.height)
+}
+
+/// How a view is scaled into its slice of the window when the aspect ratios differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaling {
+    /// Fill the slice, distorting the image
+    Stretch,
+    /// Letterbox or pillarbox the image inside the slice
+    Fit,
+    /// Fill the slice and crop whatever doesn't fit
+    Fill,
+}
+impl Scaling {
+    /// Returns the source and destination rectangles as `[min, max]` offsets.
+    fn rects(
+        self,
+        src: [u32; 2],
+        dst_min: [u32; 2],
+        dst: [u32; 2],
+    ) -> ([[u32; 3]; 2], [[u32; 3]; 2]) {
+        let rect = |min: [u32; 2], size: [u32; 2]| {
+            [[min[0], min[1], 0], [min[0] + size[0], min[1] + size[1], 1]]
+        };
+        let scale_x = dst[0] as f32 / src[0] as f32;
+        let scale_y = dst[1] as f32 / src[1] as f32;
+        let centered = |outer: [u32; 2], inner: [u32; 2]| {
+            [
+                (outer[0].saturating_sub(inner[0])) / 2,
+                (outer[1].saturating_sub(inner[1])) / 2,
+            ]
+        };
+        match self {
+            Scaling::Stretch => (rect([0, 0], src), rect(dst_min, dst)),
+            Scaling::Fit => {
+                let scale = scale_x.min(scale_y);
+                let size = [
+                    ((src[0] as f32 * scale).round() as u32).min(dst[0]),
+                    ((src[1] as f32 * scale).round() as u32).min(dst[1]),
+                ];
+                let offset = centered(dst, size);
+                (
+                    rect([0, 0], src),
+                    rect([dst_min[0] + offset[0], dst_min[1] + offset[1]], size),
+                )
+            }
+            Scaling::Fill => {
+                let scale = scale_x.max(scale_y);
+                let size = [
+                    ((dst[0] as f32 / scale).round() as u32).min(src[0]),
+                    ((dst[1] as f32 / scale).round() as u32).min(src[1]),
+                ];
+                (rect(centered(src, size), size), rect(dst_min, dst))
+            }
+        }
+    }
+}
+
+/// Mirroring of every view, for cameras whose image ends up upside down or mirrored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Flip {
+    #[value(name = "h")]
+    Horizontal,
+    #[value(name = "v")]
+    Vertical,
+    #[value(name = "hv")]
+    Both,
+}
+impl Flip {
+    /// Swaps the `[min, max]` source offsets along the flipped axes, blits and the tone mapper
+    /// both sample reversed source regions without an extra pass.
+    fn apply(self, src_offsets: &mut [[u32; 3]; 2]) {
+        let [min, max] = src_offsets;
+        if matches!(self, Flip::Horizontal | Flip::Both) {
+            std::mem::swap(&mut min[0], &mut max[0]);
+        }
+        if matches!(self, Flip::Vertical | Flip::Both) {
+            std::mem::swap(&mut min[1], &mut max[1]);
+        }
+    }
+}
+
+/// Blits every array layer of `src` into its own horizontal slice of the `[min, size]` region
+/// `dst_rect` of `dst`, left to right, scaling linearly when the sizes differ. With
+/// [`Scaling::Fit`] the uncovered parts of the region are left untouched, so they should be
+/// cleared beforehand.
+pub fn side_by_side_blit(
+    src: Arc<Image>,
+    dst: Arc<Image>,
+    dst_rect: [[u32; 2]; 2],
+    scaling: Scaling,
+    flip: Option<Flip>,
+) -> BlitImageInfo {
+    let [src_width, src_height, _] = src.extent();
+    let views = src.array_layers();
+    let regions = side_by_side_rects([src_width, src_height], views, dst_rect, scaling, flip)
+        .into_iter()
+        .enumerate()
+        .map(|(layer, (src_offsets, dst_offsets))| {
+            let layer = layer as u32;
+            ImageBlit {
+                src_subresource: ImageSubresourceLayers {
+                    array_layers: layer..layer + 1,
+                    ..src.subresource_layers()
+                },
+                src_offsets,
+                dst_subresource: dst.subresource_layers(),
+                dst_offsets,
+                ..Default::default()
+            }
+        })
+        .collect();
+    BlitImageInfo {
+        regions,
+        filter: Filter::Linear,
+        ..BlitImageInfo::images(src, dst)
+    }
+}
+
+/// The source and destination rectangles of each of the `views` array layers of a `src_size`
+/// image when laid out side by side in the `[min, size]` region `dst_rect`, see
+/// [`Scaling::rects`]. Flipping happens after scaling so every view stays in its slice and a crop
+/// stays centered.
+pub fn side_by_side_rects(
+    [src_width, src_height]: [u32; 2],
+    views: u32,
+    [dst_min, [dst_width, dst_height]]: [[u32; 2]; 2],
+    scaling: Scaling,
+    flip: Option<Flip>,
+) -> Vec<([[u32; 3]; 2], [[u32; 3]; 2])> {
+    (0..views)
+        .map(|layer| {
+            let slice_min = dst_width * layer / views;
+            let slice_width = dst_width * (layer + 1) / views - slice_min;
+            let (mut src_offsets, dst_offsets) = scaling.rects(
+                [src_width, src_height],
+                [dst_min[0] + slice_min, dst_min[1]],
+                [slice_width, dst_height],
+            );
+            if let Some(flip) = flip {
+                flip.apply(&mut src_offsets);
+            }
+            (src_offsets, dst_offsets)
+        })
+        .collect()
+}
+
+/// Creates a CME swapchain in `format`, one the connection's server supports, with one array
+/// layer of `size` per view.
+pub fn create_cme_swapchain(
+    connection: &Connection,
+    dev: &Arc<Device>,
+    format: Format,
+    size: PhysicalSize<u32>,
+    views: u32,
+) -> Result<Swapchain, SetupError> {
+    let dmatex_format = connection
+        .formats
+        .get(&format)
+        .ok_or(SetupError::DmatexFormatUnavailable(format))?;
+    // sampled by the tone mapping and NV12 passes
+    let usage = ImageUsage::TRANSFER_SRC | ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED;
+    Ok(Swapchain::new(
+        &connection.client,
+        dev,
+        &connection.render_dev,
+        DmatexSize::Dim2D(size.into()),
+        dmatex_format,
+        (views > 1).then_some(views),
+        usage,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stretch_maps_the_whole_source_onto_the_whole_slice() {
+        assert_eq!(
+            Scaling::Stretch.rects([100, 50], [10, 20], [200, 200]),
+            ([[0, 0, 0], [100, 50, 1]], [[10, 20, 0], [210, 220, 1]])
+        );
+    }
+
+    #[test]
+    fn fit_letterboxes_into_the_center() {
+        assert_eq!(
+            Scaling::Fit.rects([100, 50], [10, 20], [200, 200]),
+            ([[0, 0, 0], [100, 50, 1]], [[10, 70, 0], [210, 170, 1]])
+        );
+        // pillarboxed the other way around
+        assert_eq!(
+            Scaling::Fit.rects([50, 100], [0, 0], [200, 100]),
+            ([[0, 0, 0], [50, 100, 1]], [[75, 0, 0], [125, 100, 1]])
+        );
+    }
+
+    #[test]
+    fn fill_crops_the_center_of_the_source() {
+        assert_eq!(
+            Scaling::Fill.rects([100, 50], [10, 20], [200, 200]),
+            ([[25, 0, 0], [75, 50, 1]], [[10, 20, 0], [210, 220, 1]])
+        );
+    }
+
+    #[test]
+    fn flip_swaps_the_flipped_axes() {
+        let rect = [[10, 20, 0], [110, 70, 1]];
+        let flipped = |flip: Flip| {
+            let mut rect = rect;
+            flip.apply(&mut rect);
+            rect
+        };
+        assert_eq!(flipped(Flip::Horizontal), [[110, 20, 0], [10, 70, 1]]);
+        assert_eq!(flipped(Flip::Vertical), [[10, 70, 0], [110, 20, 1]]);
+        assert_eq!(flipped(Flip::Both), [[110, 70, 0], [10, 20, 1]]);
+    }
+
+    #[test]
+    fn side_by_side_slices_cover_the_region() {
+        let rects = side_by_side_rects([100, 100], 2, [[5, 0], [301, 100]], Scaling::Stretch, None);
+        assert_eq!(
+            rects,
+            [
+                ([[0, 0, 0], [100, 100, 1]], [[5, 0, 0], [155, 100, 1]]),
+                ([[0, 0, 0], [100, 100, 1]], [[155, 0, 0], [306, 100, 1]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn side_by_side_flips_after_cropping() {
+        let rects = side_by_side_rects(
+            [100, 100],
+            2,
+            [[0, 0], [100, 100]],
+            Scaling::Fill,
+            Some(Flip::Horizontal),
+        );
+        assert_eq!(
+            rects,
+            [
+                ([[75, 0, 0], [25, 100, 1]], [[0, 0, 0], [50, 100, 1]]),
+                ([[75, 0, 0], [25, 100, 1]], [[50, 0, 0], [100, 100, 1]]),
+            ]
+        );
+    }
+}
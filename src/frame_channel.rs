@@ -0,0 +1,133 @@
+use std::{
+    collections::VecDeque,
+    num::NonZeroUsize,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::headless::Frame;
+
+struct Shared {
+    frames: Mutex<VecDeque<Frame>>,
+    capacity: usize,
+    /// Set once the sender is gone
+    closed: AtomicBool,
+    notify: Notify,
+}
+
+/// A bounded channel of rendered frames that drops the oldest frame when full, so a slow
+/// consumer never stalls rendering.
+pub fn frame_channel(capacity: NonZeroUsize) -> (FrameSender, FrameReceiver) {
+    let capacity = capacity.get();
+    let shared = Arc::new(Shared {
+        frames: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        closed: AtomicBool::new(false),
+        notify: Notify::new(),
+    });
+    (
+        FrameSender {
+            shared: shared.clone(),
+        },
+        FrameReceiver { shared },
+    )
+}
+
+pub struct FrameSender {
+    shared: Arc<Shared>,
+}
+impl FrameSender {
+    /// Never waits, drops the oldest queued frame if the receiver fell behind.
+    pub fn send(&self, frame: Frame) {
+        let mut frames = self.shared.frames.lock().unwrap();
+        if frames.len() >= self.shared.capacity
+            && let Some(dropped) = frames.pop_front()
+        {
+            debug!(
+                index = dropped.index,
+                "the frame receiver fell behind, dropping a frame"
+            );
+        }
+        frames.push_back(frame);
+        drop(frames);
+        self.shared.notify.notify_one();
+    }
+}
+impl Drop for FrameSender {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        self.shared.notify.notify_one();
+    }
+}
+
+/// Receives the frames of a [`crate::CameraClient`], oldest first.
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+}
+impl FrameReceiver {
+    /// Waits for the next frame, `None` once the client stopped and every queued frame was
+    /// received.
+    pub async fn recv(&mut self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = self.try_recv() {
+                return Some(frame);
+            }
+            if self.shared.closed.load(Ordering::Relaxed) {
+                return self.try_recv();
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// The next frame if one is queued, without waiting.
+    pub fn try_recv(&mut self) -> Option<Frame> {
+        self.shared.frames.lock().unwrap().pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(index: u64) -> Frame {
+        Frame {
+            index,
+            width: 1,
+            height: 1,
+            data: vec![0; 4],
+        }
+    }
+
+    fn received(receiver: &mut FrameReceiver) -> Vec<u64> {
+        std::iter::from_fn(|| receiver.try_recv())
+            .map(|frame| frame.index)
+            .collect()
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_when_full() {
+        let (sender, mut receiver) = frame_channel(NonZeroUsize::new(2).unwrap());
+        for index in 0..5 {
+            sender.send(frame(index));
+        }
+        assert_eq!(received(&mut receiver), [3, 4]);
+        sender.send(frame(5));
+        assert_eq!(received(&mut receiver), [5]);
+    }
+
+    #[tokio::test]
+    async fn drains_the_queue_after_the_sender_is_gone() {
+        let (sender, mut receiver) = frame_channel(NonZeroUsize::new(4).unwrap());
+        sender.send(frame(0));
+        sender.send(frame(1));
+        drop(sender);
+        assert_eq!(receiver.recv().await.map(|frame| frame.index), Some(0));
+        assert_eq!(receiver.recv().await.map(|frame| frame.index), Some(1));
+        assert!(receiver.recv().await.is_none());
+    }
+}
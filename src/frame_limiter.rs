@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+/// Holds back `RootEvent::Frame`s that arrive ahead of a target frame rate until their deadline,
+/// so rendered frames are spaced evenly instead of following whichever server frame comes next.
+#[derive(Debug)]
+pub struct FrameLimiter {
+    interval: Duration,
+    next_frame: Instant,
+    /// Delta of the frames held since the last rendered one in seconds, `None` if none is held
+    held_delta: Option<f32>,
+}
+impl FrameLimiter {
+    pub fn new(max_fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / max_fps,
+            next_frame: Instant::now(),
+            held_delta: None,
+        }
+    }
+
+    /// Returns `None` if the frame is held until [`Self::deadline`], otherwise the time since
+    /// the last rendered frame so held frames don't slow down the camera movement.
+    pub fn accept(&mut self, delta: f32) -> Option<f32> {
+        let delta = self.held_delta.take().unwrap_or(0.0) + delta;
+        if Instant::now() < self.next_frame {
+            self.held_delta = Some(delta);
+            return None;
+        }
+        Some(self.advance(delta))
+    }
+
+    /// When the held frame is due, `None` if no frame is held.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.held_delta.map(|_| self.next_frame)
+    }
+
+    /// Renders the held frame once [`Self::deadline`] passed, like [`Self::accept`].
+    pub fn release(&mut self) -> Option<f32> {
+        let delta = self.held_delta.take()?;
+        Some(self.advance(delta))
+    }
+
+    fn advance(&mut self, delta: f32) -> f32 {
+        // don't try to catch up after a slow frame
+        self.next_frame = (self.next_frame + self.interval).max(Instant::now());
+        delta
+    }
+}
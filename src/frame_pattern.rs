@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::SetupError;
+
+/// An `--out` file name pattern like `frame_{:06}.png`, the headless frame index is formatted
+/// into its single placeholder. `{}` writes the plain index, `{:N}` pads it to N digits and
+/// `{:0N}` pads it with zeros.
+#[derive(Debug, Clone)]
+pub struct FramePattern {
+    prefix: String,
+    suffix: String,
+    width: usize,
+    zero_pad: bool,
+}
+impl FramePattern {
+    /// Whether `--out` is meant as a pattern rather than a directory or file.
+    pub fn is_pattern(path: &Path) -> bool {
+        path.to_string_lossy().contains(['{', '}'])
+    }
+
+    pub fn parse(path: &Path) -> Result<Self, SetupError> {
+        let invalid = |reason: &str| SetupError::OutPattern(path.to_path_buf(), reason.to_owned());
+        let pattern = path
+            .to_str()
+            .ok_or_else(|| invalid("it isn't valid UTF-8"))?;
+        let (prefix, rest) = pattern
+            .split_once('{')
+            .ok_or_else(|| invalid("it has no {} placeholder"))?;
+        let (spec, suffix) = rest
+            .split_once('}')
+            .ok_or_else(|| invalid("its placeholder isn't closed"))?;
+        if prefix.contains('}') || spec.contains('{') || suffix.contains(['{', '}']) {
+            return Err(invalid("it needs exactly one placeholder"));
+        }
+        let (width, zero_pad) = match spec.strip_prefix(':') {
+            None if spec.is_empty() => (0, false),
+            Some(width) if !width.is_empty() && width.bytes().all(|b| b.is_ascii_digit()) => (
+                width
+                    .parse()
+                    .map_err(|_| invalid("its padding is too wide"))?,
+                width.starts_with('0'),
+            ),
+            _ => return Err(invalid("the placeholder has to be {}, {:N} or {:0N}")),
+        };
+        Ok(Self {
+            prefix: prefix.to_owned(),
+            suffix: suffix.to_owned(),
+            width,
+            zero_pad,
+        })
+    }
+
+    /// The directory the frames are written to, if the pattern has one.
+    pub fn dir(&self) -> Option<&Path> {
+        Path::new(&self.prefix)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+    }
+
+    pub fn path(&self, index: u64) -> PathBuf {
+        let (prefix, suffix, width) = (&self.prefix, &self.suffix, self.width);
+        PathBuf::from(if self.zero_pad {
+            format!("{prefix}{index:0width$}{suffix}")
+        } else {
+            format!("{prefix}{index:width$}{suffix}")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> Result<FramePattern, SetupError> {
+        FramePattern::parse(Path::new(pattern))
+    }
+
+    #[test]
+    fn formats_the_index_into_the_placeholder() {
+        let pattern = parse("out/frame_{:06}.png").unwrap();
+        assert_eq!(pattern.path(7), Path::new("out/frame_000007.png"));
+        assert_eq!(pattern.dir(), Some(Path::new("out")));
+        assert_eq!(parse("{}.png").unwrap().path(42), Path::new("42.png"));
+        assert_eq!(parse("f{:4}.png").unwrap().path(7), Path::new("f   7.png"));
+    }
+
+    #[test]
+    fn has_no_dir_without_a_parent() {
+        assert_eq!(parse("frame_{}.png").unwrap().dir(), None);
+    }
+
+    #[test]
+    fn rejects_malformed_placeholders() {
+        for pattern in [
+            "frame.png",
+            "frame_{.png",
+            "frame_}{.png",
+            "frame_{}_{}.png",
+            "frame_{:x}.png",
+            "frame_{:}.png",
+            "frame_{:99999999999999999999999}.png",
+        ] {
+            assert!(
+                matches!(parse(pattern), Err(SetupError::OutPattern(..))),
+                "{pattern} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn only_braces_make_a_pattern() {
+        assert!(FramePattern::is_pattern(Path::new("frame_{}.png")));
+        assert!(!FramePattern::is_pattern(Path::new("frames/")));
+    }
+}
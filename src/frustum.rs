@@ -0,0 +1,52 @@
+use glam::Vec3;
+use stardust_xr_fusion::{
+    drawable::{Line, LinePoint},
+    values::color::rgba_linear,
+};
+
+use crate::projection::Projection;
+
+/// How far in front of the camera the frustum is drawn, the real far plane is usually too far
+/// away to see.
+const DISTANCE: f32 = 0.5;
+const THICKNESS: f32 = 0.002;
+
+/// Lines outlining the camera's frustum in camera space, for a [`Lines`] parented to the
+/// camera: the four edges from the camera to the corners of the image at [`DISTANCE`] and the
+/// rectangle between those corners. Orthographic edges run parallel from the camera plane.
+///
+/// [`Lines`]: stardust_xr_fusion::drawable::Lines
+pub fn frustum_lines(projection: &Projection, aspect_ratio: f32) -> Vec<Line> {
+    let inverse = projection.matrix(aspect_ratio).inverse();
+    let ndc_corners = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+    let corners = ndc_corners.map(|[x, y]| {
+        // any depth works, only the direction or the x and y of the unprojected point are used
+        let point = inverse.project_point3(Vec3::new(x, y, 0.5));
+        match projection.ortho_height {
+            Some(_) => point.with_z(-DISTANCE),
+            None => point * (DISTANCE / -point.z),
+        }
+    });
+    let origins = corners.map(|corner| match projection.ortho_height {
+        Some(_) => corner.with_z(0.0),
+        None => Vec3::ZERO,
+    });
+    let line = |points: &[Vec3], cyclic| Line {
+        points: points
+            .iter()
+            .map(|point| LinePoint {
+                point: (*point).into(),
+                thickness: THICKNESS,
+                color: rgba_linear!(1.0, 0.8, 0.0, 1.0),
+            })
+            .collect(),
+        cyclic,
+    };
+    let mut lines: Vec<_> = origins
+        .iter()
+        .zip(&corners)
+        .map(|(origin, corner)| line(&[*origin, *corner], false))
+        .collect();
+    lines.push(line(&corners, true));
+    lines
+}
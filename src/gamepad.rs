@@ -0,0 +1,93 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+use glam::Vec2;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(8);
+/// Stick deflection below this is treated as centered
+const DEADZONE: f32 = 0.15;
+
+/// Stick and trigger positions of the first connected gamepad, all zero without one.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GamepadInput {
+    /// Left stick, x to the right and y forward
+    pub movement: Vec2,
+    /// Right stick, x to the right and y up
+    pub look: Vec2,
+    /// Right trigger from 0 to 1, speeds up movement
+    pub boost: f32,
+    /// Left trigger zooms in, the left bumper zooms out
+    pub zoom: f32,
+}
+
+/// Polls gamepads on their own thread for `--gamepad`, the stardust loop reads the latest
+/// [`GamepadInput`] every frame.
+pub struct Gamepad {
+    input: Arc<Mutex<GamepadInput>>,
+}
+impl Gamepad {
+    /// Stops polling on shutdown. If gamepads aren't supported this only warns and the input
+    /// stays zero.
+    pub fn spawn(shutdown: CancellationToken) -> Self {
+        let input = Arc::<Mutex<GamepadInput>>::default();
+        let shared = input.clone();
+        thread::spawn(move || {
+            let mut gilrs = match Gilrs::new() {
+                Ok(gilrs) => gilrs,
+                Err(err) => {
+                    warn!("gamepads are unavailable: {err}");
+                    return;
+                }
+            };
+            if gilrs.gamepads().next().is_none() {
+                info!("no gamepad connected yet");
+            }
+            while !shutdown.is_cancelled() {
+                while let Some(event) = gilrs.next_event() {
+                    match event.event {
+                        EventType::Connected => {
+                            info!(name = gilrs.gamepad(event.id).name(), "gamepad connected");
+                        }
+                        EventType::Disconnected => info!("gamepad disconnected"),
+                        _ => {}
+                    }
+                }
+                *shared.lock().unwrap() = gilrs
+                    .gamepads()
+                    .next()
+                    .map(|(_, gamepad)| {
+                        let stick = |x, y| {
+                            let stick = Vec2::new(gamepad.value(x), gamepad.value(y));
+                            if stick.length() < DEADZONE {
+                                Vec2::ZERO
+                            } else {
+                                stick.clamp_length_max(1.0)
+                            }
+                        };
+                        let trigger =
+                            |button| gamepad.button_data(button).map_or(0.0, |data| data.value());
+                        GamepadInput {
+                            movement: stick(Axis::LeftStickX, Axis::LeftStickY),
+                            look: stick(Axis::RightStickX, Axis::RightStickY),
+                            boost: trigger(Button::RightTrigger2),
+                            zoom: trigger(Button::LeftTrigger2)
+                                - gamepad.is_pressed(Button::LeftTrigger) as i32 as f32,
+                        }
+                    })
+                    .unwrap_or_default();
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        Self { input }
+    }
+
+    pub fn input(&self) -> GamepadInput {
+        *self.input.lock().unwrap()
+    }
+}
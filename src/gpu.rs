@@ -0,0 +1,228 @@
+use std::{cmp::Reverse, path::PathBuf, sync::Arc};
+
+use stardust_xr_cme::{dmatex::Dmatex, render_device::RenderDevice};
+use tracing::info;
+use vulkano::{
+    device::{
+        Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
+        physical::PhysicalDevice,
+    },
+    instance::Instance,
+};
+use winit::event_loop::EventLoop;
+
+use crate::error::{SetupContext, SetupError};
+
+pub enum GpuSelector {
+    Index(usize),
+    Name(String),
+}
+
+/// Picks the physical device to render with, which always has to be the one the server renders
+/// with since dmatex interop only works between the same device.
+pub fn select_physical_device(
+    instance: &Arc<Instance>,
+    render_dev: &RenderDevice,
+    selector: Option<GpuSelector>,
+) -> Result<Arc<PhysicalDevice>, SetupError> {
+    let server_dev = render_dev
+        .get_physical_device(instance)
+        .ok_or(SetupError::NoMatchingPhysicalDevice)?;
+    let Some(selector) = selector else {
+        return Ok(server_dev);
+    };
+    let devices: Vec<_> = instance
+        .enumerate_physical_devices()
+        .vk_context("enumerating physical devices")?
+        .collect();
+    let describe =
+        |i: usize, dev: &PhysicalDevice| format!("{i}: {}", dev.properties().device_name);
+    let chosen = match &selector {
+        GpuSelector::Index(index) => devices.iter().enumerate().find(|(i, _)| i == index),
+        GpuSelector::Name(name) => devices.iter().enumerate().find(|(_, dev)| {
+            dev.properties()
+                .device_name
+                .to_lowercase()
+                .contains(&name.to_lowercase())
+        }),
+    };
+    let compatible = || {
+        devices
+            .iter()
+            .enumerate()
+            .filter(|(_, dev)| dev.handle() == server_dev.handle())
+            .map(|(i, dev)| describe(i, dev))
+            .collect::<Vec<_>>()
+    };
+    let Some((index, chosen)) = chosen else {
+        return Err(SetupError::NoSuchGpu {
+            available: devices
+                .iter()
+                .enumerate()
+                .map(|(i, dev)| describe(i, dev))
+                .collect(),
+        });
+    };
+    if chosen.handle() != server_dev.handle() {
+        return Err(SetupError::IncompatibleGpu {
+            requested: describe(index, chosen),
+            compatible: compatible(),
+        });
+    }
+    info!(gpu = describe(index, chosen), "selected gpu");
+    Ok(chosen.clone())
+}
+
+/// The queue family all work is submitted to and the one window images are presented from,
+/// which are the same unless no usable family can present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFamilies {
+    pub render: u32,
+    pub present: u32,
+}
+impl QueueFamilies {
+    pub fn is_split(&self) -> bool {
+        self.render != self.present
+    }
+}
+
+/// The DRM render node of `phys_dev`, e.g. `/dev/dri/renderD129`. `None` if the driver doesn't
+/// report it through `VK_EXT_physical_device_drm`.
+pub fn render_node(phys_dev: &PhysicalDevice) -> Option<PathBuf> {
+    let properties = phys_dev.properties();
+    if properties.has_render != Some(true) {
+        return None;
+    }
+    Some(PathBuf::from(format!(
+        "/dev/dri/renderD{}",
+        properties.render_minor?
+    )))
+}
+
+/// Picks the queue families, the render family has to support `required`. Families are scored:
+/// presenting to `event_loop`'s windows counts the most since it avoids a second queue, then
+/// with `prefer_graphics` families that also support graphics, shader passes can't run on
+/// transfer-only queues, and otherwise dedicated transfer families, which copy without competing
+/// with rendering. If the best family can't present the first one that can is used for that.
+/// `requested` skips the search but is checked the same way.
+pub fn select_queue_family(
+    phys_dev: &PhysicalDevice,
+    required: QueueFlags,
+    prefer_graphics: bool,
+    event_loop: Option<&EventLoop<()>>,
+    requested: Option<u32>,
+) -> Result<QueueFamilies, SetupError> {
+    let families = phys_dev.queue_family_properties();
+    let presents = |index: u32| {
+        event_loop.is_none_or(|event_loop| {
+            phys_dev
+                .presentation_support(index, event_loop)
+                .unwrap_or(false)
+        })
+    };
+    let render = match requested {
+        Some(index) => {
+            let Some(family) = families.get(index as usize) else {
+                return Err(SetupError::UnusableQueueFamily(
+                    index,
+                    format!("the gpu only has {} queue families", families.len()),
+                ));
+            };
+            if !family.queue_flags.contains(required) {
+                return Err(SetupError::UnusableQueueFamily(
+                    index,
+                    format!(
+                        "it supports {:?} but {:?} is needed",
+                        family.queue_flags, required
+                    ),
+                ));
+            }
+            info!(index, "using the requested queue family");
+            index
+        }
+        None => {
+            let (index, flags) = families
+                .iter()
+                .enumerate()
+                .map(|(index, family)| (index as u32, family.queue_flags))
+                .filter(|(_, flags)| flags.contains(required))
+                .max_by_key(|(index, flags)| {
+                    let mut score = 0;
+                    if presents(*index) {
+                        score += 4;
+                    }
+                    if prefer_graphics && flags.contains(QueueFlags::GRAPHICS) {
+                        score += 2;
+                    }
+                    if !prefer_graphics
+                        && !flags.intersects(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+                    {
+                        score += 1;
+                    }
+                    // the lowest index wins ties
+                    (score, Reverse(*index))
+                })
+                .ok_or(SetupError::NoUsableQueueFamily(required))?;
+            info!(index, ?flags, "selected queue family");
+            index
+        }
+    };
+    let present = if presents(render) {
+        render
+    } else {
+        let present = (0..families.len() as u32)
+            .find(|index| presents(*index))
+            .ok_or(SetupError::NoPresentQueueFamily)?;
+        info!(
+            render,
+            present, "queue family {render} can't present, presenting from a separate queue"
+        );
+        present
+    };
+    Ok(QueueFamilies { render, present })
+}
+
+/// Creates the device with a render and a present queue, which are the same queue unless the
+/// `families` are split, and the extensions dmatex interop needs unless `interop` is false, plus
+/// swapchain support for a window.
+pub fn create_device(
+    phys_dev: &Arc<PhysicalDevice>,
+    families: QueueFamilies,
+    swapchain: bool,
+    interop: bool,
+) -> Result<(Arc<Device>, Arc<Queue>, Arc<Queue>), SetupError> {
+    let (interop_extensions, interop_features) = if interop {
+        (
+            Dmatex::required_device_exts(),
+            Dmatex::required_device_features(),
+        )
+    } else {
+        Default::default()
+    };
+    let mut queue_family_indices = vec![families.render];
+    if families.is_split() {
+        queue_family_indices.push(families.present);
+    }
+    let (dev, mut queues) = Device::new(
+        phys_dev.clone(),
+        DeviceCreateInfo {
+            enabled_extensions: DeviceExtensions {
+                khr_swapchain: swapchain,
+                ..Default::default()
+            } | interop_extensions,
+            enabled_features: interop_features,
+            queue_create_infos: queue_family_indices
+                .into_iter()
+                .map(|queue_family_index| QueueCreateInfo {
+                    queue_family_index,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        },
+    )
+    .vk_context("creating the device")?;
+    let queue = queues.next().unwrap();
+    let present_queue = queues.next().unwrap_or_else(|| queue.clone());
+    Ok((dev, queue, present_queue))
+}
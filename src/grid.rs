@@ -0,0 +1,60 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+use winit::dpi::PhysicalSize;
+
+/// How the window is split into cells, one camera each, numbered row by row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+}
+impl Grid {
+    pub fn count(&self) -> usize {
+        (self.columns * self.rows) as usize
+    }
+
+    /// Size of a single cell, cells at the right and bottom edge may be a pixel larger.
+    pub fn cell_size(&self, size: PhysicalSize<u32>) -> PhysicalSize<u32> {
+        PhysicalSize::new(size.width / self.columns, size.height / self.rows)
+    }
+
+    /// Returns the `[min, size]` of `cell` within an image of `size`.
+    pub fn cell_rect(&self, size: PhysicalSize<u32>, cell: usize) -> [[u32; 2]; 2] {
+        let column = cell as u32 % self.columns;
+        let row = cell as u32 / self.columns;
+        let min = [
+            size.width * column / self.columns,
+            size.height * row / self.rows,
+        ];
+        let max = [
+            size.width * (column + 1) / self.columns,
+            size.height * (row + 1) / self.rows,
+        ];
+        [min, [max[0] - min[0], max[1] - min[1]]]
+    }
+}
+impl TryFrom<String> for Grid {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+impl FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| value.parse::<u32>().ok().filter(|value| *value > 0);
+        match s.split_once('x') {
+            Some((columns, rows)) => match (parse(columns), parse(rows)) {
+                (Some(columns), Some(rows)) => Ok(Self { columns, rows }),
+                _ => Err(format!(
+                    "expected positive column and row counts, got {s:?}"
+                )),
+            },
+            None => Err(format!("expected COLUMNSxROWS, got {s:?}")),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::{fs, io::ErrorKind, path::PathBuf, sync::Arc};
+
+use stardust_xr_cme::swapchain::Swapchain;
+use tracing::{error, info};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    device::Device,
+    format::Format,
+    image::Image,
+    memory::allocator::StandardMemoryAllocator,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    accumulate::Accumulator,
+    connection::Connection,
+    error::{FrameError, SetupContext, SetupError},
+    frame::{FrameSink, create_cme_swapchain},
+    frame_pattern::FramePattern,
+    raw_out::RawOut,
+    readback::{AlphaMode, Readback, unpremultiply, write_rgba8_png},
+    stream::Encoder,
+    tone_map::{ColorCorrection, CorrectedImage},
+    yuv::Nv12Converter,
+};
+
+/// Where headless frames end up.
+pub enum Destination {
+    /// Numbered files in this directory
+    Directory(PathBuf),
+    /// Exactly this file, for a single frame
+    File(PathBuf),
+    /// Files named by formatting the frame index into a pattern
+    Pattern(FramePattern),
+    /// Piped into ffmpeg, needs the NV12 conversion
+    Stream(Encoder),
+    /// Written as raw RGBA8 to stdout or a named pipe
+    Raw(RawOut),
+    /// Handed to an embedding application, see [`crate::CameraClient`]
+    Callback(FrameCallback),
+}
+
+/// Receives every frame rendered by a [`crate::CameraClient`].
+pub type FrameCallback = Box<dyn FnMut(Frame) + Send>;
+
+/// A rendered frame read back from the gpu.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Counts up from 0
+    pub index: u64,
+    /// Width of all views side by side
+    pub width: u32,
+    pub height: u32,
+    /// sRGB encoded RGBA8 pixels with premultiplied alpha, row after row
+    pub data: Vec<u8>,
+}
+
+/// Writes every rendered frame to a directory as a numbered PNG instead of showing it in a
+/// window, or as raw NV12 with `--yuv`, or streams it. Multiple views are written side by side.
+pub struct PngSink {
+    cme_swapchain: Swapchain,
+    size: PhysicalSize<u32>,
+    views: u32,
+    memalloc: Arc<StandardMemoryAllocator>,
+    readback: Readback,
+    /// Replaces the PNG readback when set
+    nv12: Option<Nv12Converter>,
+    destination: Destination,
+    frame_index: u64,
+    max_frames: Option<u64>,
+    /// Set once the encoder or the raw output's reader stopped accepting frames
+    stream_closed: bool,
+    /// The `--exposure` and `--gamma` pass, read back instead of the CME image
+    corrected: Option<CorrectedImage>,
+    /// Set with `--accumulate`, every written frame averages its subframes
+    accumulator: Option<Accumulator>,
+    /// Whether written PNG and raw frames keep the premultiplied alpha, see `--alpha`
+    alpha: AlphaMode,
+    /// CME images that were submitted before. The server only draws into an image once it was
+    /// submitted, so until then it holds nothing worth reading back.
+    drawn: Vec<DrawnImage>,
+    /// This frame's image and the subframe it was drawn with, `None` if it wasn't drawn into yet
+    /// and is skipped instead of read back
+    current: Option<(Arc<Image>, Option<u32>)>,
+    /// The `--accumulate` subframe the next submitted image is drawn with
+    next_subframe: u32,
+}
+
+/// A CME image that was submitted before, with the `--accumulate` subframe whose jitter the
+/// server drew it with.
+struct DrawnImage {
+    image: Arc<Image>,
+    subframe: u32,
+}
+impl PngSink {
+    pub fn new(
+        cme_swapchain: Swapchain,
+        memalloc: Arc<StandardMemoryAllocator>,
+        size: PhysicalSize<u32>,
+        views: u32,
+        destination: Destination,
+        max_frames: Option<u64>,
+        format: Format,
+        nv12: Option<Nv12Converter>,
+    ) -> Result<Self, SetupError> {
+        let out_dir = match &destination {
+            Destination::Directory(out_dir) => Some(out_dir.as_path()),
+            Destination::File(path) => path.parent().filter(|dir| !dir.as_os_str().is_empty()),
+            Destination::Pattern(pattern) => pattern.dir(),
+            Destination::Stream(_) | Destination::Raw(_) | Destination::Callback(_) => None,
+        };
+        if let Some(out_dir) = out_dir {
+            fs::create_dir_all(out_dir)
+                .map_err(|err| SetupError::OutputDir(out_dir.to_path_buf(), err))?;
+        }
+        let readback = Readback::new(memalloc.clone(), size, views, format)
+            .vk_context("allocating the readback buffer")?;
+        Ok(Self {
+            cme_swapchain,
+            size,
+            views,
+            memalloc,
+            readback,
+            nv12,
+            destination,
+            frame_index: 0,
+            max_frames,
+            stream_closed: false,
+            corrected: None,
+            accumulator: None,
+            alpha: AlphaMode::Premult,
+            drawn: Vec::new(),
+            current: None,
+            next_subframe: 0,
+        })
+    }
+
+    /// Runs `correction` over every frame before it's read back.
+    pub fn with_color_correction(
+        mut self,
+        correction: Option<ColorCorrection>,
+    ) -> Result<Self, SetupError> {
+        self.corrected = correction
+            .map(|correction| {
+                CorrectedImage::new(
+                    &self.memalloc,
+                    self.size,
+                    self.views,
+                    self.readback.format,
+                    correction,
+                )
+            })
+            .transpose()?;
+        Ok(self)
+    }
+
+    /// Writes PNG and raw frames with `alpha`, callbacks and NV12 aren't affected.
+    pub fn with_alpha(mut self, alpha: AlphaMode) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Averages every `subframes` rendered frames into one written frame.
+    pub fn with_accumulation(mut self, subframes: Option<u32>) -> Self {
+        self.accumulator = subframes.map(Accumulator::new);
+        self
+    }
+
+    /// The `--accumulate` subframe the image just submitted is drawn with, the submit has to
+    /// carry its [`crate::accumulate::jitter`]. `None` without `--accumulate`.
+    pub fn subframe(&self) -> Option<u32> {
+        let subframes = self.accumulator.as_ref()?.subframes();
+        Some((self.next_subframe + subframes - 1) % subframes)
+    }
+
+    /// True once `max_frames` frames have been written or the stream closed.
+    pub fn is_done(&self) -> bool {
+        self.stream_closed
+            || self
+                .max_frames
+                .is_some_and(|max_frames| self.frame_index >= max_frames)
+    }
+
+    /// Replaces the CME swapchain with one shared with the new connection's server.
+    pub fn reconnect(
+        &mut self,
+        connection: &Connection,
+        dev: &Arc<Device>,
+    ) -> Result<(), SetupError> {
+        self.cme_swapchain = create_cme_swapchain(
+            connection,
+            dev,
+            connection.cme_format,
+            self.size,
+            self.views,
+        )?;
+        self.drawn.clear();
+        self.next_subframe = 0;
+        if connection.cme_format != self.readback.format {
+            self.readback = Readback::new(
+                self.memalloc.clone(),
+                self.size,
+                self.views,
+                connection.cme_format,
+            )
+            .vk_context("allocating the readback buffer")?;
+            if let Some(corrected) = &mut self.corrected {
+                *corrected = CorrectedImage::new(
+                    &self.memalloc,
+                    self.size,
+                    self.views,
+                    connection.cme_format,
+                    corrected.correction(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+impl FrameSink for PngSink {
+    fn cme_swapchain(&mut self) -> &mut Swapchain {
+        &mut self.cme_swapchain
+    }
+
+    fn acquire(&mut self) -> bool {
+        !self.is_done()
+    }
+
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        let drawn = self
+            .drawn
+            .iter()
+            .find(|drawn| Arc::ptr_eq(&drawn.image, &image))
+            .map(|drawn| drawn.subframe);
+        self.current = Some((image.clone(), drawn));
+        if drawn.is_none() {
+            return Ok(());
+        }
+        let image = match &self.corrected {
+            Some(corrected) => corrected.record(builder, image)?,
+            None => image,
+        };
+        match &self.nv12 {
+            Some(nv12) => nv12.record(builder, image),
+            None => self.readback.record(builder, image),
+        }
+    }
+
+    fn complete(&mut self) {
+        let Some((image, drawn_subframe)) = self.current.take() else {
+            return;
+        };
+        // submitted now, the server draws into it with this subframe's jitter before it comes up
+        // again
+        let subframe = self.next_subframe;
+        self.next_subframe = self
+            .accumulator
+            .as_ref()
+            .map_or(0, |accumulator| (subframe + 1) % accumulator.subframes());
+        match self
+            .drawn
+            .iter_mut()
+            .find(|drawn| Arc::ptr_eq(&drawn.image, &image))
+        {
+            Some(drawn) => drawn.subframe = subframe,
+            None => self.drawn.push(DrawnImage { image, subframe }),
+        }
+        let Some(drawn_subframe) = drawn_subframe else {
+            return;
+        };
+        if let Some(accumulator) = &mut self.accumulator {
+            match self.readback.rgba8() {
+                Ok(data) => accumulator.add(drawn_subframe, &data),
+                Err(err) => error!("failed to read back a subframe: {err}"),
+            }
+            if !accumulator.is_done() {
+                return;
+            }
+        }
+        let path = match &mut self.destination {
+            Destination::Directory(out_dir) => {
+                let extension = if self.nv12.is_some() { "nv12" } else { "png" };
+                out_dir.join(format!("{:06}.{extension}", self.frame_index))
+            }
+            Destination::File(path) => path.clone(),
+            Destination::Pattern(pattern) => pattern.path(self.frame_index),
+            Destination::Stream(encoder) => {
+                let nv12 = self.nv12.as_ref().unwrap();
+                if let Err(err) = nv12.frame().and_then(|frame| encoder.write_frame(&frame)) {
+                    error!("the encoder stopped accepting frames: {err}");
+                    self.stream_closed = true;
+                }
+                self.frame_index += 1;
+                return;
+            }
+            Destination::Raw(raw_out) => {
+                match rgba8(&self.readback, &mut self.accumulator, self.alpha) {
+                    Ok(data) => {
+                        if let Err(err) = raw_out.write_frame(&data) {
+                            if err.kind() == ErrorKind::BrokenPipe {
+                                info!("the raw output's reader went away");
+                            } else {
+                                error!("failed to write a raw frame: {err}");
+                            }
+                            self.stream_closed = true;
+                        }
+                    }
+                    Err(err) => error!("failed to read back a frame: {err}"),
+                }
+                self.frame_index += 1;
+                return;
+            }
+            Destination::Callback(callback) => {
+                match rgba8(&self.readback, &mut self.accumulator, AlphaMode::Premult) {
+                    Ok(data) => callback(Frame {
+                        index: self.frame_index,
+                        width: self.size.width * self.views,
+                        height: self.size.height,
+                        data,
+                    }),
+                    Err(err) => error!("failed to read back a frame: {err}"),
+                }
+                self.frame_index += 1;
+                return;
+            }
+        };
+        let result = match &self.nv12 {
+            Some(nv12) => nv12.write(&path).map_err(|err| err.to_string()),
+            None => {
+                let size = PhysicalSize::new(self.size.width * self.views, self.size.height);
+                rgba8(&self.readback, &mut self.accumulator, self.alpha)
+                    .map_err(|err| err.to_string())
+                    .and_then(|data| {
+                        write_rgba8_png(&path, size, &data).map_err(|err| err.to_string())
+                    })
+            }
+        };
+        match result {
+            Ok(()) => info!(path = %path.display(), "wrote frame"),
+            Err(err) => error!(path = %path.display(), "failed to write frame: {err}"),
+        }
+        self.frame_index += 1;
+    }
+}
+
+/// The frame to write, averaged if accumulating. Averaging happens before unpremultiplying,
+/// since only premultiplied colors average correctly.
+fn rgba8(
+    readback: &Readback,
+    accumulator: &mut Option<Accumulator>,
+    alpha: AlphaMode,
+) -> Result<Vec<u8>, FrameError> {
+    let mut data = match accumulator {
+        Some(accumulator) => accumulator.take_average(),
+        None => readback.rgba8()?,
+    };
+    if alpha == AlphaMode::Straight {
+        unpremultiply(&mut data);
+    }
+    Ok(data)
+}
@@ -0,0 +1,53 @@
+//! A camera for stardust, rendering the scene to a window, to files, a stream or, through
+//! [`CameraClient`], to another application.
+
+mod accumulate;
+mod app;
+mod bench;
+mod bracket;
+mod camera_path;
+pub mod cli;
+mod client;
+mod config;
+mod connection;
+mod diagnostics;
+pub mod error;
+mod export;
+mod fly_camera;
+mod frame;
+mod frame_channel;
+mod frame_limiter;
+mod frame_pattern;
+mod frustum;
+mod gamepad;
+mod gpu;
+mod grid;
+mod headless;
+mod model_watch;
+mod no_interop;
+mod orbit_camera;
+mod overlay;
+mod projection;
+mod raw_out;
+mod readback;
+mod replay;
+mod stardust;
+mod state;
+mod stats;
+mod status;
+mod stream;
+mod test_pattern;
+mod tone_map;
+mod validation;
+mod views;
+mod watchdog;
+mod window;
+mod yuv;
+
+pub use app::run;
+pub use client::{CameraClient, CameraClientHandle, CameraClientOptions, CameraControl};
+pub use fly_camera::FlyCamera;
+pub use frame_channel::FrameReceiver;
+pub use headless::Frame;
+pub use projection::Projection;
+pub use views::ViewLayout;
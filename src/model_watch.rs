@@ -0,0 +1,53 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use crate::{cli::ModelResource, error::SetupError};
+
+/// Watches the file of `--model` for `--watch-model`, so the previews can be recreated whenever
+/// the asset is saved instead of restarting the client.
+pub struct ModelWatcher {
+    changed: Arc<AtomicBool>,
+    _watcher: RecommendedWatcher,
+}
+impl ModelWatcher {
+    pub fn new(model: &ModelResource) -> Result<Self, SetupError> {
+        let path = model
+            .local_path()
+            .ok_or_else(|| SetupError::ModelNotLocal(model.to_string()))?;
+        let changed = Arc::new(AtomicBool::new(false));
+        let mut watcher = notify::recommended_watcher({
+            let changed = changed.clone();
+            let path = path.clone();
+            move |event: notify::Result<Event>| match event {
+                Ok(event)
+                    if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                        && event.paths.contains(&path) =>
+                {
+                    changed.store(true, Ordering::Relaxed);
+                }
+                Ok(_) => {}
+                Err(err) => warn!("failed to watch the model: {err}"),
+            }
+        })
+        .map_err(|err| SetupError::WatchModel(path.clone(), err))?;
+        // the directory, since editors often save by replacing the file
+        watcher
+            .watch(path.parent().unwrap_or(&path), RecursiveMode::NonRecursive)
+            .map_err(|err| SetupError::WatchModel(path.clone(), err))?;
+        info!(path = %path.display(), "watching the model");
+        Ok(Self {
+            changed,
+            _watcher: watcher,
+        })
+    }
+
+    /// Whether the model file changed since the last call.
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
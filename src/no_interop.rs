@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use tracing::{error, info, warn};
+use vulkano::{
+    Validated, VulkanError, VulkanLibrary,
+    command_buffer::{
+        AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage,
+        allocator::StandardCommandBufferAllocator,
+    },
+    device::{Device, Queue, QueueFlags},
+    image::{Image, ImageUsage},
+    instance::{Instance, InstanceCreateInfo},
+    memory::allocator::StandardMemoryAllocator,
+    swapchain::{self, Surface, SwapchainCreateInfo, SwapchainPresentInfo},
+    sync::GpuFuture,
+};
+use winit::{
+    application::ApplicationHandler,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop},
+    platform::run_on_demand::EventLoopExtRunOnDemand,
+    window::{Window, WindowId},
+};
+
+use crate::{
+    error::{SetupContext, SetupError},
+    gpu::{create_device, select_queue_family},
+    overlay::Overlay,
+    window::{TITLE, choose_surface_format},
+};
+
+/// Gray, so the placeholder can't be mistaken for a black camera image.
+const PLACEHOLDER: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
+
+/// Opens a window showing a placeholder and the interop failure for `--no-interop`, instead of
+/// exiting because Dmatex images can't be shared with the server. Renders on any gpu that can
+/// present, without a camera in the scene. `event_loop` is reused if it was already created.
+pub fn run(err: SetupError, event_loop: Option<&mut EventLoop<()>>) -> Result<(), SetupError> {
+    error!("dmatex interop failed, showing a placeholder: {err}");
+    let mut created = None;
+    let event_loop = match event_loop {
+        Some(event_loop) => event_loop,
+        None => created.insert(EventLoop::new().vk_context("creating the event loop")?),
+    };
+    let library = VulkanLibrary::new().vk_context("loading the vulkan library")?;
+    let instance = Instance::new(
+        library,
+        InstanceCreateInfo {
+            enabled_extensions: Surface::required_extensions(&*event_loop)
+                .vk_context("querying surface extensions")?,
+            ..Default::default()
+        },
+    )
+    .vk_context("creating the instance")?;
+    // the placeholder presents from the queue it clears on
+    let (phys_dev, queue_families) = instance
+        .enumerate_physical_devices()
+        .vk_context("enumerating physical devices")?
+        .find_map(|phys_dev| {
+            let families = select_queue_family(
+                &phys_dev,
+                QueueFlags::TRANSFER,
+                false,
+                Some(&*event_loop),
+                None,
+            )
+            .ok()
+            .filter(|families| !families.is_split())?;
+            Some((phys_dev, families))
+        })
+        .ok_or(SetupError::NoUsableQueueFamily(QueueFlags::TRANSFER))?;
+    info!(
+        gpu = phys_dev.properties().device_name,
+        "rendering the placeholder"
+    );
+    let (dev, queue, _) = create_device(&phys_dev, queue_families, true, false)?;
+    let mut overlay = Overlay::new(Arc::new(StandardMemoryAllocator::new_default(dev.clone())));
+    overlay.set_text("dmatex interop failed, see the log");
+    let mut placeholder = Placeholder {
+        instance,
+        cballoc: Arc::new(StandardCommandBufferAllocator::new(
+            dev.clone(),
+            Default::default(),
+        )),
+        overlay,
+        dev,
+        queue,
+        window: None,
+        error: None,
+    };
+    tokio::task::block_in_place(|| {
+        event_loop
+            .run_app_on_demand(&mut placeholder)
+            .vk_context("running the event loop")
+    })?;
+    placeholder.error.map_or(Ok(()), Err)
+}
+
+struct PlaceholderWindow {
+    window: Arc<Window>,
+    swapchain: Arc<swapchain::Swapchain>,
+    images: Vec<Arc<Image>>,
+    recreate: bool,
+}
+
+struct Placeholder {
+    instance: Arc<Instance>,
+    dev: Arc<Device>,
+    queue: Arc<Queue>,
+    cballoc: Arc<StandardCommandBufferAllocator>,
+    overlay: Overlay,
+    window: Option<PlaceholderWindow>,
+    error: Option<SetupError>,
+}
+impl Placeholder {
+    fn create_window(&self, event_loop: &ActiveEventLoop) -> Result<PlaceholderWindow, SetupError> {
+        let window = Arc::new(
+            event_loop
+                .create_window(Window::default_attributes().with_title(TITLE))
+                .vk_context("creating the window")?,
+        );
+        let surface = Surface::from_window(self.instance.clone(), window.clone())
+            .vk_context("creating the window surface")?;
+        let (image_format, _) = choose_surface_format(
+            &self
+                .dev
+                .physical_device()
+                .surface_formats(&surface, Default::default())
+                .vk_context("querying surface formats")?,
+            None,
+        )?;
+        let surface_capabilities = self
+            .dev
+            .physical_device()
+            .surface_capabilities(&surface, Default::default())
+            .vk_context("querying surface capabilities")?;
+        let size = window.inner_size();
+        let (swapchain, images) = swapchain::Swapchain::new(
+            self.dev.clone(),
+            surface,
+            SwapchainCreateInfo {
+                min_image_count: surface_capabilities.min_image_count.max(2),
+                image_format,
+                image_extent: [size.width.max(1), size.height.max(1)],
+                image_usage: ImageUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+        )
+        .vk_context("creating the window swapchain")?;
+        Ok(PlaceholderWindow {
+            window,
+            swapchain,
+            images,
+            recreate: false,
+        })
+    }
+
+    fn draw(&mut self) {
+        let Some(output) = &mut self.window else {
+            return;
+        };
+        let size = output.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        if output.recreate {
+            let (swapchain, images) = output
+                .swapchain
+                .recreate(SwapchainCreateInfo {
+                    image_extent: size.into(),
+                    ..output.swapchain.create_info()
+                })
+                .unwrap();
+            output.swapchain = swapchain;
+            output.images = images;
+            output.recreate = false;
+        }
+        let (index, suboptimal, acquire) =
+            match swapchain::acquire_next_image(output.swapchain.clone(), None) {
+                Ok(acquired) => acquired,
+                Err(Validated::Error(VulkanError::OutOfDate)) => {
+                    output.recreate = true;
+                    return;
+                }
+                Err(err) => panic!("failed to acquire swapchain image: {err}"),
+            };
+        output.recreate |= suboptimal;
+        let image = output.images[index as usize].clone();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.cballoc.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .clear_color_image(ClearColorImageInfo {
+                clear_value: PLACEHOLDER.into(),
+                ..ClearColorImageInfo::image(image.clone())
+            })
+            .unwrap();
+        if let Err(err) = self.overlay.draw(&mut builder, image) {
+            warn!("failed to draw the placeholder text: {err}");
+        }
+        let result = acquire
+            .then_execute(self.queue.clone(), builder.build().unwrap())
+            .unwrap()
+            .then_swapchain_present(
+                self.queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(output.swapchain.clone(), index),
+            )
+            .then_signal_fence_and_flush()
+            .and_then(|future| future.wait(None));
+        match result {
+            Ok(()) => {}
+            Err(Validated::Error(VulkanError::OutOfDate)) => output.recreate = true,
+            Err(err) => warn!("failed to present the placeholder: {err}"),
+        }
+    }
+}
+impl ApplicationHandler for Placeholder {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+        match self.create_window(event_loop) {
+            Ok(window) => {
+                self.overlay.set_scale_factor(window.window.scale_factor());
+                window.window.request_redraw();
+                self.window = Some(window);
+            }
+            Err(err) => {
+                error!("{err}");
+                self.error = Some(err);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested | WindowEvent::Destroyed => {
+                self.window = None;
+                event_loop.exit();
+            }
+            WindowEvent::Resized(_) => {
+                if let Some(output) = &mut self.window {
+                    output.recreate = true;
+                    output.window.request_redraw();
+                }
+            }
+            WindowEvent::RedrawRequested => self.draw(),
+            _ => {}
+        }
+    }
+}
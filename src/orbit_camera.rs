@@ -0,0 +1,106 @@
+use glam::{EulerRot, Quat, Vec3};
+
+use crate::fly_camera::FlyCamera;
+
+const ROTATE_SENSITIVITY: f32 = 0.005;
+/// Pan distance per pixel of mouse motion, relative to the radius
+const PAN_SENSITIVITY: f32 = 0.002;
+/// Radius multiplier per scrolled line
+const ZOOM_STEP: f32 = 0.9;
+const MIN_RADIUS: f32 = 0.01;
+/// Used when the orbit can't be derived from the camera's position
+pub const DEFAULT_RADIUS: f32 = 1.0;
+
+/// Orbit camera state, rotating around and looking at `pivot`. Fed by winit input, its pose is
+/// written into the [`FlyCamera`] the stardust loop reads.
+#[derive(Debug, Clone)]
+pub struct OrbitCamera {
+    pub pivot: Vec3,
+    /// Rotation around the Y axis in radians
+    pub yaw: f32,
+    /// Rotation around the X axis in radians, clamped to avoid flipping over
+    pub pitch: f32,
+    /// Distance from the pivot
+    pub radius: f32,
+    rotating: bool,
+    panning: bool,
+}
+impl OrbitCamera {
+    /// Orbits `pivot` starting from `position`.
+    pub fn looking_at(pivot: Vec3, position: Vec3) -> Self {
+        let offset = position - pivot;
+        let radius = offset.length();
+        if radius < MIN_RADIUS {
+            return Self::new(pivot, 0.0, 0.0, DEFAULT_RADIUS);
+        }
+        Self::new(
+            pivot,
+            offset.x.atan2(offset.z),
+            (-offset.y / radius).asin(),
+            radius,
+        )
+    }
+
+    /// Orbits a pivot `radius` in front of `fly_camera`, keeping its current view.
+    pub fn from_fly_camera(fly_camera: &FlyCamera, radius: f32) -> Self {
+        let pivot = fly_camera.position - fly_camera.orientation() * Vec3::Z * radius;
+        Self::new(pivot, fly_camera.yaw, fly_camera.pitch, radius)
+    }
+
+    fn new(pivot: Vec3, yaw: f32, pitch: f32, radius: f32) -> Self {
+        Self {
+            pivot,
+            yaw,
+            pitch: clamp_pitch(pitch),
+            radius,
+            rotating: false,
+            panning: false,
+        }
+    }
+
+    pub fn orientation(&self) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0)
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.pivot + self.orientation() * Vec3::Z * self.radius
+    }
+
+    /// Moves `fly_camera` to this camera's pose.
+    pub fn apply(&self, fly_camera: &mut FlyCamera) {
+        fly_camera.position = self.position();
+        fly_camera.yaw = self.yaw;
+        fly_camera.pitch = self.pitch;
+        fly_camera.roll = 0.0;
+    }
+
+    /// Rotating is only active while the left mouse button is held.
+    pub fn set_rotating(&mut self, rotating: bool) {
+        self.rotating = rotating;
+    }
+
+    /// Panning is only active while the right mouse button is held.
+    pub fn set_panning(&mut self, panning: bool) {
+        self.panning = panning;
+    }
+
+    pub fn handle_mouse_motion(&mut self, (dx, dy): (f64, f64)) {
+        if self.rotating {
+            self.yaw -= dx as f32 * ROTATE_SENSITIVITY;
+            self.pitch = clamp_pitch(self.pitch - dy as f32 * ROTATE_SENSITIVITY);
+        }
+        if self.panning {
+            let pan = Vec3::new(-dx as f32, dy as f32, 0.0) * PAN_SENSITIVITY * self.radius;
+            self.pivot += self.orientation() * pan;
+        }
+    }
+
+    /// Zooms in for positive `lines`.
+    pub fn handle_scroll(&mut self, lines: f32) {
+        self.radius = (self.radius * ZOOM_STEP.powf(lines)).max(MIN_RADIUS);
+    }
+}
+
+fn clamp_pitch(pitch: f32) -> f32 {
+    pitch.clamp(-89f32.to_radians(), 89f32.to_radians())
+}
@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, BufferImageCopy, CopyBufferToImageInfo, PrimaryAutoCommandBuffer,
+    },
+    image::Image,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::error::{FrameContext, FrameError};
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// Screen pixels per font pixel at a scale factor of 1
+const BASE_SCALE: f64 = 2.0;
+const PADDING: u32 = 2;
+const MAX_CHARS: u32 = 48;
+/// Lines of the keybinding help, any text only has one
+const MAX_LINES: u32 = 16;
+const LINE_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+/// Size of the text box in font pixels, the height of the largest one
+const WIDTH: u32 = MAX_CHARS * (GLYPH_WIDTH + 1) + PADDING * 2;
+const HEIGHT: u32 = MAX_LINES * LINE_HEIGHT - 1 + PADDING * 2;
+/// Premultiplied, identical in every channel so it works for both RGBA and BGRA swapchains
+const BACKGROUND: [u8; 4] = [0, 0, 0, 180];
+const FOREGROUND: [u8; 4] = [255, 255, 255, 255];
+
+/// A tiny bitmap text renderer, copying a CPU rasterized line of text, or the keybinding help,
+/// into the top left corner of the window image.
+pub struct Overlay {
+    pub enabled: bool,
+    memalloc: Arc<StandardMemoryAllocator>,
+    buffer: Subbuffer<[u8]>,
+    /// Screen pixels per font pixel, follows the window's scale factor
+    scale: u32,
+    text: String,
+    /// Shown instead of `text` while set, even if not [`Self::enabled`]
+    help: Option<Vec<String>>,
+}
+impl Overlay {
+    pub fn new(memalloc: Arc<StandardMemoryAllocator>) -> Self {
+        let scale = BASE_SCALE as u32;
+        Self {
+            enabled: false,
+            buffer: allocate(&memalloc, scale),
+            memalloc,
+            scale,
+            text: String::new(),
+            help: None,
+        }
+    }
+
+    /// Sizes the text for a window with `scale_factor`, so it stays readable on HiDPI displays.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        let scale = ((BASE_SCALE * scale_factor).round() as u32).max(1);
+        if scale != self.scale {
+            self.scale = scale;
+            self.buffer = allocate(&self.memalloc, scale);
+        }
+    }
+
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+    }
+
+    /// Shows `help` one entry per line, or hides it if it's already shown. Returns whether it's
+    /// shown now.
+    pub fn toggle_help(&mut self, help: Vec<String>) -> bool {
+        self.help = match self.help {
+            Some(_) => None,
+            None => Some(help),
+        };
+        self.help.is_some()
+    }
+
+    /// Records the copy of the overlay into `dst` if enabled or showing the help, must be called after anything else
+    /// that writes to `dst` was recorded.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dst: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        if self.enabled || self.help.is_some() {
+            self.draw(builder, dst)?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::record`] but ignores [`Self::enabled`], for status text that always shows.
+    pub fn draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dst: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        let [dst_width, dst_height, _] = dst.extent();
+        let lines = self
+            .help
+            .as_ref()
+            .map_or(1, |help| help.len().min(MAX_LINES as usize));
+        let box_height = lines as u32 * LINE_HEIGHT - 1 + PADDING * 2;
+        let [width, height] = [WIDTH * self.scale, box_height * self.scale];
+        if dst_width < width || dst_height < height {
+            return Ok(());
+        }
+        self.rasterize(height)?;
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: [BufferImageCopy {
+                    image_subresource: dst.subresource_layers(),
+                    image_extent: [width, height, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyBufferToImageInfo::buffer_image(self.buffer.clone(), dst)
+            })
+            .frame_context("copying the overlay")?;
+        Ok(())
+    }
+
+    /// Rasterizes the top `height` screen pixels of the box, the part that gets copied.
+    fn rasterize(&mut self, height: u32) -> Result<(), FrameError> {
+        let scale = self.scale;
+        let width = WIDTH * scale;
+        let mut pixels = self.buffer.write().map_err(FrameError::HostAccess)?;
+        for pixel in pixels[..(width * height * 4) as usize].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&BACKGROUND);
+        }
+        let lines: Vec<&str> = match &self.help {
+            Some(help) => help.iter().map(String::as_str).collect(),
+            None => vec![&self.text],
+        };
+        for (line, text) in lines.into_iter().take(MAX_LINES as usize).enumerate() {
+            let origin_y = PADDING + line as u32 * LINE_HEIGHT;
+            for (i, c) in text.chars().take(MAX_CHARS as usize).enumerate() {
+                let origin_x = PADDING + i as u32 * (GLYPH_WIDTH + 1);
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    for col in 0..GLYPH_WIDTH {
+                        if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                            continue;
+                        }
+                        let x = (origin_x + col) * scale;
+                        let y = (origin_y + row as u32) * scale;
+                        for dy in 0..scale {
+                            for dx in 0..scale {
+                                let offset = (((y + dy) * width + x + dx) * 4) as usize;
+                                pixels[offset..offset + 4].copy_from_slice(&FOREGROUND);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn allocate(memalloc: &Arc<StandardMemoryAllocator>, scale: u32) -> Subbuffer<[u8]> {
+    Buffer::new_slice::<u8>(
+        memalloc.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        (WIDTH * HEIGHT * scale * scale * 4) as u64,
+    )
+    .unwrap()
+}
+
+/// 5x7 glyphs, one byte per row with the leftmost pixel in bit 4. Unknown characters are blank.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '*' => [0x00, 0x0A, 0x04, 0x1F, 0x04, 0x0A, 0x00],
+        '?' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        _ => [0; 7],
+    }
+}
@@ -0,0 +1,167 @@
+use glam::Mat4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    /// Vertical field of view in radians
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    /// Visible height in meters for an orthographic projection, `fov_y` is unused then
+    pub ortho_height: Option<f32>,
+    /// Width of a pixel relative to its height, 1 for square pixels
+    pub pixel_aspect: f32,
+    /// Off-axis shift of the frustum as a fraction of the image's width and height, for sheared
+    /// frustums like portals
+    pub lens_shift: [f32; 2],
+}
+impl Projection {
+    /// Shifts the frustum by `offset` pixels of a `size` pixel image, for jittered subframes.
+    pub fn jittered(&self, offset: [f32; 2], size: [u32; 2]) -> Self {
+        let mut projection = *self;
+        projection.lens_shift[0] += offset[0] / size[0] as f32;
+        projection.lens_shift[1] += offset[1] / size[1] as f32;
+        projection
+    }
+
+    /// `aspect_ratio` is the image's width over its height in pixels.
+    pub fn matrix(&self, aspect_ratio: f32) -> Mat4 {
+        let aspect_ratio = aspect_ratio * self.pixel_aspect;
+        match self.ortho_height {
+            Some(height) => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect_ratio;
+                let shift_x = self.lens_shift[0] * half_width * 2.0;
+                let shift_y = self.lens_shift[1] * half_height * 2.0;
+                Mat4::orthographic_rh(
+                    shift_x - half_width,
+                    shift_x + half_width,
+                    shift_y - half_height,
+                    shift_y + half_height,
+                    self.near,
+                    self.far,
+                )
+            }
+            None => {
+                let mut matrix =
+                    Mat4::perspective_rh(self.fov_y, aspect_ratio, self.near, self.far);
+                // (right + left) / (right - left) of the shifted frustum, likewise for y
+                matrix.z_axis.x = self.lens_shift[0] * 2.0;
+                matrix.z_axis.y = self.lens_shift[1] * 2.0;
+                matrix
+            }
+        }
+    }
+}
+
+/// Range the scroll wheel zooms within, in degrees.
+const MIN_ZOOM_FOV: f32 = 10.0;
+const MAX_ZOOM_FOV: f32 = 120.0;
+/// FOV multiplier per scrolled line
+const ZOOM_STEP: f32 = 0.9;
+/// Scrolled lines per second at a fully pressed zoom trigger
+const GAMEPAD_ZOOM_RATE: f32 = 4.0;
+/// How quickly the FOV follows the target, per second
+const ZOOM_RATE: f32 = 15.0;
+
+/// Target vertical FOV set by the scroll wheel, the stardust loop eases the projection towards
+/// it every frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Zoom {
+    /// In radians
+    target_fov_y: f32,
+    /// The startup FOV [`Self::reset`] zooms back to
+    initial_fov_y: f32,
+}
+impl Zoom {
+    pub fn new(projection: &Projection) -> Self {
+        Self {
+            target_fov_y: projection.fov_y,
+            initial_fov_y: projection.fov_y,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.target_fov_y = self.initial_fov_y;
+    }
+
+    /// Zooms in for positive `lines`.
+    pub fn handle_scroll(&mut self, lines: f32) {
+        self.target_fov_y = (self.target_fov_y * ZOOM_STEP.powf(lines))
+            .clamp(MIN_ZOOM_FOV.to_radians(), MAX_ZOOM_FOV.to_radians());
+    }
+
+    /// Zooms in for positive `zoom` from the gamepad, see [`GamepadInput::zoom`].
+    ///
+    /// [`GamepadInput::zoom`]: crate::gamepad::GamepadInput::zoom
+    pub fn handle_gamepad(&mut self, zoom: f32, delta: f32) {
+        if zoom != 0.0 {
+            self.handle_scroll(zoom * GAMEPAD_ZOOM_RATE * delta);
+        }
+    }
+
+    /// Moves `projection`'s FOV towards the target, `delta` is in seconds.
+    pub fn update(&self, projection: &mut Projection, delta: f32) {
+        let t = 1.0 - (-ZOOM_RATE * delta).exp();
+        projection.fov_y += (self.target_fov_y - projection.fov_y) * t;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    fn projection(ortho_height: Option<f32>, lens_shift: [f32; 2]) -> Projection {
+        Projection {
+            fov_y: 90f32.to_radians(),
+            near: 0.1,
+            far: 100.0,
+            ortho_height,
+            pixel_aspect: 1.0,
+            lens_shift,
+        }
+    }
+
+    /// Where a point straight ahead of the camera ends up in normalized device coordinates.
+    fn center(projection: &Projection) -> [f32; 2] {
+        let point = projection
+            .matrix(1.0)
+            .project_point3(Vec3::new(0.0, 0.0, -1.0));
+        [point.x, point.y]
+    }
+
+    fn assert_close([x, y]: [f32; 2], [expected_x, expected_y]: [f32; 2]) {
+        assert!(
+            (x - expected_x).abs() < 1e-5 && (y - expected_y).abs() < 1e-5,
+            "expected [{expected_x}, {expected_y}], got [{x}, {y}]"
+        );
+    }
+
+    #[test]
+    fn lens_shift_moves_the_frustum() {
+        assert_close(center(&projection(None, [0.0, 0.0])), [0.0, 0.0]);
+        // a frustum shifted right by a quarter of the image sees the center a quarter to the left
+        assert_close(center(&projection(None, [0.25, 0.0])), [-0.5, 0.0]);
+        assert_close(center(&projection(None, [0.0, -0.1])), [0.0, 0.2]);
+    }
+
+    #[test]
+    fn lens_shift_matches_between_perspective_and_orthographic() {
+        for lens_shift in [[0.25, 0.0], [-0.1, 0.3]] {
+            assert_close(
+                center(&projection(Some(2.0), lens_shift)),
+                center(&projection(None, lens_shift)),
+            );
+        }
+    }
+
+    #[test]
+    fn jitter_shifts_by_whole_pixels() {
+        let jittered = projection(None, [0.25, 0.0]).jittered([1.0, -0.5], [100, 50]);
+        assert_close(jittered.lens_shift, [0.26, -0.01]);
+        // a pixel is 2 / 100 wide in normalized device coordinates
+        let [x, y] = center(&jittered);
+        assert_close([x, y], [-0.5 - 0.02, 0.02]);
+    }
+}
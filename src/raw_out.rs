@@ -0,0 +1,42 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use tracing::info;
+use winit::dpi::PhysicalSize;
+
+use crate::error::SetupError;
+
+/// Writes headless frames for `--raw-out` as tightly packed sRGB RGBA8 without any header, every
+/// frame is `height` rows of `width * 4` bytes.
+pub struct RawOut {
+    writer: Box<dyn Write + Send>,
+}
+impl RawOut {
+    /// `-` is stdout, anything else a file or named pipe, opening a pipe waits for its reader.
+    /// `size` is the size of a whole frame with all views side by side.
+    pub fn open(path: &Path, size: PhysicalSize<u32>) -> Result<Self, SetupError> {
+        let writer: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path).map_err(|err| SetupError::RawOut(path.to_path_buf(), err))?)
+        };
+        info!(
+            width = size.width,
+            height = size.height,
+            stride = size.width * 4,
+            "writing raw rgba frames, read them with ffmpeg -f rawvideo -pix_fmt rgba \
+             -video_size {}x{} -i -",
+            size.width,
+            size.height
+        );
+        Ok(Self { writer })
+    }
+
+    pub fn write_frame(&mut self, rgba8: &[u8]) -> io::Result<()> {
+        self.writer.write_all(rgba8)?;
+        self.writer.flush()
+    }
+}
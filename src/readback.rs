@@ -0,0 +1,234 @@
+use std::{error::Error, fs::File, io::BufWriter, path::Path, sync::Arc};
+
+use clap::ValueEnum;
+use vulkano::{
+    Validated,
+    buffer::{AllocateBufferError, Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo, PrimaryAutoCommandBuffer},
+    format::Format,
+    image::Image,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    error::{FrameContext, FrameError},
+    frame::HDR_CME_FORMAT,
+};
+
+/// How the alpha channel of written frames relates to their color channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum AlphaMode {
+    /// The color is already multiplied by alpha, as the server renders it
+    #[default]
+    Premult,
+    /// The color is divided by alpha, what most image editors expect
+    Straight,
+}
+
+/// A host visible copy of a CME image, written out as a PNG with the views side by side.
+///
+/// This is the only readback path, there is no image export that would need a slower fallback.
+/// [`Self::record`] copies into a linear host visible buffer, and the auto command buffer inserts
+/// the layout transition and the barrier for that copy. [`Self::side_by_side`] only maps the
+/// buffer once the frame's fence has been waited on, and `Subbuffer::read` invalidates
+/// non-coherent memory first. [`Self::rgba8`] encodes UNORM and tone maps HDR images to sRGB, so
+/// saved PNGs match the window.
+pub struct Readback {
+    buffer: Subbuffer<[u8]>,
+    /// Size of a single view
+    size: PhysicalSize<u32>,
+    views: u32,
+    /// Format of the CME images, either the 8-bit SRGB or the HDR one
+    pub format: Format,
+}
+impl Readback {
+    pub fn new(
+        memalloc: Arc<StandardMemoryAllocator>,
+        size: PhysicalSize<u32>,
+        views: u32,
+        format: Format,
+    ) -> Result<Self, Validated<AllocateBufferError>> {
+        let len = size.width as u64 * size.height as u64 * views as u64 * format.block_size();
+        let buffer = Buffer::new_slice::<u8>(
+            memalloc,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            len,
+        )?;
+        Ok(Self {
+            buffer,
+            size,
+            views,
+            format,
+        })
+    }
+
+    /// Whether the buffer can take a CME image of this size and format.
+    pub fn fits(&self, size: PhysicalSize<u32>, views: u32, format: Format) -> bool {
+        self.size == size && self.views == views && self.format == format
+    }
+
+    /// Records the copy of every layer of `image` into the readback buffer.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                image,
+                self.buffer.clone(),
+            ))
+            .frame_context("recording the readback copy")?;
+        Ok(())
+    }
+
+    /// Reorders the layer after layer readback into rows spanning all views.
+    fn side_by_side(&self) -> Result<Vec<u8>, FrameError> {
+        let data = self.buffer.read().map_err(FrameError::HostAccess)?;
+        if self.views == 1 {
+            return Ok(data.to_vec());
+        }
+        let row_len = self.size.width as usize * self.format.block_size() as usize;
+        let layer_len = row_len * self.size.height as usize;
+        let mut out = Vec::with_capacity(data.len());
+        for row in 0..self.size.height as usize {
+            for layer in 0..self.views as usize {
+                let start = layer * layer_len + row * row_len;
+                out.extend_from_slice(&data[start..start + row_len]);
+            }
+        }
+        Ok(out)
+    }
+
+    /// The views side by side as sRGB encoded RGBA8 pixels, tone mapped if HDR. Must only be
+    /// called once the recorded copy has completed.
+    pub fn rgba8(&self) -> Result<Vec<u8>, FrameError> {
+        let data = self.side_by_side()?;
+        Ok(if self.format == HDR_CME_FORMAT {
+            tone_map(&data)
+        } else {
+            to_srgb_rgba(data, self.format)
+        })
+    }
+
+    /// Must only be called once the recorded copy has completed.
+    pub fn write_png(&self, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        write_rgba8_png(
+            path,
+            PhysicalSize::new(self.size.width * self.views, self.size.height),
+            &self.rgba8()?,
+        )?;
+        Ok(())
+    }
+}
+
+/// Writes sRGB encoded RGBA8 pixels as a PNG.
+pub fn write_rgba8_png(
+    path: &Path,
+    size: PhysicalSize<u32>,
+    rgba8: &[u8],
+) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba8)?;
+    writer.finish()
+}
+
+/// Converts R16G16B16A16_SFLOAT pixels to sRGB encoded 8-bit ones, using the same Reinhard
+/// operator as the window's tone mapping pass.
+fn tone_map(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(2)
+        .map(|half| f16_to_f32(u16::from_le_bytes([half[0], half[1]])))
+        .enumerate()
+        .map(|(i, value)| {
+            let value = value.max(0.0);
+            let encoded = if i % 4 == 3 {
+                value.min(1.0)
+            } else {
+                linear_to_srgb(value / (1.0 + value))
+            };
+            (encoded * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Reorders BGRA pixels to RGBA and encodes UNORM ones, which hold linear values.
+fn to_srgb_rgba(mut data: Vec<u8>, format: Format) -> Vec<u8> {
+    let bgra = matches!(format, Format::B8G8R8A8_SRGB | Format::B8G8R8A8_UNORM);
+    let linear = matches!(format, Format::R8G8B8A8_UNORM | Format::B8G8R8A8_UNORM);
+    if !bgra && !linear {
+        return data;
+    }
+    for pixel in data.chunks_exact_mut(4) {
+        if bgra {
+            pixel.swap(0, 2);
+        }
+        if linear {
+            for channel in &mut pixel[..3] {
+                *channel = (linear_to_srgb(*channel as f32 / 255.0) * 255.0).round() as u8;
+            }
+        }
+    }
+    data
+}
+
+/// Divides the color of premultiplied, sRGB encoded RGBA8 pixels by their alpha, in linear space.
+/// Fully transparent pixels end up black.
+pub fn unpremultiply(rgba8: &mut [u8]) {
+    for pixel in rgba8.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        if alpha == 255 {
+            continue;
+        }
+        if alpha == 0 {
+            pixel[..3].fill(0);
+            continue;
+        }
+        let alpha = alpha as f32 / 255.0;
+        for channel in &mut pixel[..3] {
+            let linear = (srgb_to_linear(*channel as f32 / 255.0) / alpha).min(1.0);
+            *channel = (linear_to_srgb(linear) * 255.0).round() as u8;
+        }
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1F;
+    let mantissa = (half & 0x3FF) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::VecDeque,
+    fs, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{error, info};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    format::Format,
+    image::Image,
+    memory::allocator::StandardMemoryAllocator,
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{error::FrameError, readback::Readback};
+
+/// Frame rate the replay ring is sized for without `--max-fps`
+pub const DEFAULT_REPLAY_FPS: f32 = 60.0;
+
+/// Keeps the window's last frames for `--replay-seconds`, read back every frame into a ring of
+/// host visible buffers and only written out as a PNG sequence when F9 asks for a clip.
+pub struct ReplayRing {
+    memalloc: Arc<StandardMemoryAllocator>,
+    capacity: usize,
+    /// Oldest first, each copy completed once the frame after it is rendered
+    frames: VecDeque<Readback>,
+    dump: bool,
+}
+impl ReplayRing {
+    pub fn new(memalloc: Arc<StandardMemoryAllocator>, capacity: usize) -> Self {
+        info!(frames = capacity, "keeping frames for replay");
+        Self {
+            memalloc,
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+            dump: false,
+        }
+    }
+
+    /// Writes out the kept frames before the next one is recorded.
+    pub fn request_dump(&mut self) {
+        self.dump = true;
+    }
+
+    /// Records the copy of this frame's CME image, reusing the oldest buffer once the ring is
+    /// full. Must only be called once the previous frame completed, a requested clip is written
+    /// out first.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<Image>,
+        size: PhysicalSize<u32>,
+        views: u32,
+        format: Format,
+    ) -> Result<(), FrameError> {
+        if mem::take(&mut self.dump) {
+            self.dump_clip();
+        }
+        let oldest = if self.frames.len() >= self.capacity {
+            self.frames.pop_front()
+        } else {
+            None
+        };
+        let readback = match oldest.filter(|readback| readback.fits(size, views, format)) {
+            Some(readback) => readback,
+            None => match Readback::new(self.memalloc.clone(), size, views, format) {
+                Ok(readback) => readback,
+                Err(err) => {
+                    error!("failed to allocate a replay buffer: {err}");
+                    return Ok(());
+                }
+            },
+        };
+        readback.record(builder, image)?;
+        self.frames.push_back(readback);
+        Ok(())
+    }
+
+    /// Hands the kept frames to a thread writing them out, the ring refills meanwhile.
+    fn dump_clip(&mut self) {
+        if self.frames.is_empty() {
+            return;
+        }
+        let frames: Vec<_> = self.frames.drain(..).collect();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let dir = PathBuf::from(format!(
+            "replay-{}.{:03}",
+            timestamp.as_secs(),
+            timestamp.subsec_millis()
+        ));
+        info!(frames = frames.len(), dir = %dir.display(), "saving replay");
+        thread::spawn(move || write_clip(&dir, &frames));
+    }
+}
+
+fn write_clip(dir: &Path, frames: &[Readback]) {
+    if let Err(err) = fs::create_dir_all(dir) {
+        error!(dir = %dir.display(), "failed to create the replay directory: {err}");
+        return;
+    }
+    for (index, frame) in frames.iter().enumerate() {
+        let path = dir.join(format!("{index:06}.png"));
+        if let Err(err) = frame.write_png(&path) {
+            error!(path = %path.display(), "failed to save replay frame: {err}");
+            return;
+        }
+    }
+    info!(frames = frames.len(), dir = %dir.display(), "saved replay");
+}
@@ -0,0 +1,933 @@
+use std::{
+    collections::BTreeMap,
+    mem,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use glam::{Mat4, Vec3};
+use stardust_xr_fusion::{
+    AsyncEventHandle, AsyncEventLoop, ClientState,
+    camera::{Camera, CameraAspect, View},
+    drawable::{
+        DmatexSubmitInfo, Lines, LinesAspect, MaterialParameter, Model, ModelPart, ModelPartAspect,
+    },
+    node::NodeError,
+    root::{RootAspect, RootEvent},
+    spatial::{SpatialAspect, Transform},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, error, info, trace_span, warn};
+use vulkano::{format::Format, instance::Instance};
+
+use crate::{
+    accumulate,
+    bench::Bench,
+    bracket::{Bracket, BracketShot},
+    camera_path::CameraPath,
+    cli::{ModelResource, SkipPolicy},
+    config::MaterialValue,
+    connection::Connection,
+    error::{FrameError, SetupError},
+    export::ExportSocket,
+    fly_camera::{FlyCamera, FlyCameras},
+    frame::Renderer,
+    frame_limiter::FrameLimiter,
+    frustum::frustum_lines,
+    gamepad::Gamepad,
+    headless::PngSink,
+    model_watch::ModelWatcher,
+    projection::{Projection, Zoom},
+    state::SavedState,
+    stats::{FrameStats, SessionStats},
+    status::Status,
+    tone_map::ColorCorrection,
+    views::ViewLayout,
+    watchdog::{self, Watchdog},
+    window::Output,
+};
+
+/// Without any events for this long the connection gets probed.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// How often the window is redrawn while waiting to reconnect.
+const STATUS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Where the stardust loop sends its rendered frames.
+pub enum Target {
+    Window(Arc<Mutex<Option<Output>>>),
+    Headless(PngSink),
+}
+
+/// What [`Target::render`] did with a frame.
+enum Rendered {
+    /// The submit info and image extent of every cell, `None` for cells that were skipped
+    Cells(Vec<Option<(DmatexSubmitInfo, [u32; 3])>>),
+    /// The target can't take a frame right now, e.g. while the window is minimized
+    Skipped,
+    /// The headless output wrote all requested frames
+    Finished,
+}
+
+impl Target {
+    /// Format of the CME images just rendered, the window can switch away from the connection's.
+    fn cme_format(&self, connection: &Connection) -> Format {
+        match self {
+            Target::Window(output) => output
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or(connection.cme_format, Output::cme_format),
+            Target::Headless(_) => connection.cme_format,
+        }
+    }
+
+    /// Passes the frame stats on to the window title, see [`Output::update_title_stats`].
+    fn update_title_stats(&self, fps: f64, resolution: [u32; 2]) {
+        if let Target::Window(output) = self
+            && let Some(output) = output.lock().unwrap().as_mut()
+        {
+            output.update_title_stats(fps, resolution);
+        }
+    }
+
+    /// The `--accumulate` subframe the images just rendered are drawn with, see
+    /// [`PngSink::subframe`].
+    fn subframe(&self) -> Option<u32> {
+        match self {
+            Target::Window(_) => None,
+            Target::Headless(sink) => sink.subframe(),
+        }
+    }
+
+    /// Renders one frame of `cell_count` cells into the target. `overlay_text` is shown in the
+    /// window's overlay.
+    fn render(
+        &mut self,
+        renderer: &mut Renderer,
+        connection: &Connection,
+        cell_count: usize,
+        screenshot: &AtomicBool,
+        bracket_shot: Option<&BracketShot>,
+        overlay_text: String,
+    ) -> Result<Rendered, SetupError> {
+        match self {
+            Target::Window(output) => {
+                let mut output_lock = output.lock().unwrap();
+                let Some(output) = output_lock.as_mut() else {
+                    return Ok(Rendered::Skipped);
+                };
+                if !output.has_area() {
+                    return Ok(Rendered::Skipped);
+                }
+                let recreated = if output.recreate {
+                    // the last frame may still be writing into the old CME swapchain
+                    renderer
+                        .wait_in_flight()
+                        .and_then(|()| output.recreate_swapchains(connection, &renderer.dev))
+                } else if output.suboptimal_due() {
+                    renderer
+                        .wait_in_flight()
+                        .and_then(|()| output.recreate_suboptimal())
+                } else {
+                    Ok(())
+                };
+                if skip_failed(recreated)?.is_none() {
+                    return Ok(Rendered::Skipped);
+                }
+                if screenshot.swap(false, Ordering::Relaxed) {
+                    output.request_screenshot();
+                }
+                if let Some(shot) = bracket_shot {
+                    output.request_bracket_shot(shot);
+                }
+                output.overlay.set_text(overlay_text);
+                let mut rendered = Vec::with_capacity(cell_count);
+                for cell in 0..cell_count {
+                    output.set_cell(Some(cell));
+                    rendered.push(skip_failed(renderer.render(output))?.flatten());
+                }
+                output.frame_rendered();
+                if output.lost {
+                    return Err(SetupError::DeviceLost);
+                }
+                Ok(Rendered::Cells(rendered))
+            }
+            Target::Headless(sink) => {
+                if sink.is_done() {
+                    return Ok(Rendered::Finished);
+                }
+                Ok(Rendered::Cells(vec![
+                    skip_failed(renderer.render(sink))?.flatten(),
+                ]))
+            }
+        }
+    }
+}
+
+enum LoopExit {
+    Shutdown,
+    Disconnected,
+}
+
+/// The model showing a camera's image in front of it.
+struct Preview {
+    panel: ModelPart,
+    /// The `unlit` parameter last set on `panel`
+    unlit: bool,
+    _model: Model,
+}
+
+/// The timeline points the panel samples the image the camera is about to draw at.
+///
+/// On the image's timeline the client signals `acquire_point` once it is done with the image,
+/// the draw waits on it and signals `release_point`, and the CME swapchain waits on
+/// `release_point` before handing the image out again and signals the next acquire point right
+/// after it. The panel acquires on `release_point` so it only ever shows the finished draw,
+/// never the previous contents or a partial one. It only reads the image, so it releases at that
+/// same, already reached point: any later point is either the swapchain's own next acquire point
+/// or one nothing waits on, and signalling either would break the timeline.
+fn panel_submit_info(submit_info: &DmatexSubmitInfo) -> DmatexSubmitInfo {
+    DmatexSubmitInfo {
+        dmatex_id: submit_info.dmatex_id,
+        acquire_point: submit_info.release_point,
+        release_point: submit_info.release_point,
+    }
+}
+
+/// A camera with its preview, one per grid cell.
+struct CameraCell {
+    /// `None` with `--no-preview`
+    preview: Option<Preview>,
+    camera: Camera,
+    /// Frustum outline and the `[fov_y, aspect_ratio]` it was built for, while shown
+    frustum: Option<(Lines, [f32; 2])>,
+}
+impl CameraCell {
+    /// Creates, rebuilds or removes the frustum outline to match `show` and the projection.
+    fn update_frustum(
+        &mut self,
+        show: bool,
+        projection: &Projection,
+        aspect_ratio: f32,
+    ) -> Result<(), NodeError> {
+        if !show {
+            self.frustum = None;
+            return Ok(());
+        }
+        let key = [projection.fov_y, aspect_ratio];
+        match &mut self.frustum {
+            Some((_, built_for)) if *built_for == key => {}
+            Some((lines, built_for)) => {
+                lines.set_lines(&frustum_lines(projection, aspect_ratio))?;
+                *built_for = key;
+            }
+            None => {
+                let lines = Lines::create(
+                    &self.camera,
+                    Transform::identity(),
+                    &frustum_lines(projection, aspect_ratio),
+                )?;
+                self.frustum = Some((lines, key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the panel's `unlit` parameter again if it was toggled since.
+    fn update_unlit(&mut self, unlit: bool) -> Result<(), NodeError> {
+        if let Some(preview) = &mut self.preview
+            && preview.unlit != unlit
+        {
+            preview
+                .panel
+                .set_material_parameter("unlit", MaterialParameter::Bool(unlit))?;
+            preview.unlit = unlit;
+        }
+        Ok(())
+    }
+
+    fn submit(&self, submit_info: DmatexSubmitInfo, views: &[View]) -> Result<(), NodeError> {
+        if let Some(preview) = &self.preview {
+            preview.panel.set_material_parameter(
+                "diffuse",
+                MaterialParameter::Dmatex(panel_submit_info(&submit_info)),
+            )?;
+        }
+        self.camera.request_draw(submit_info, views)
+    }
+}
+
+pub struct StardustLoop {
+    pub renderer: Renderer,
+    pub target: Target,
+    pub instance: Arc<Instance>,
+    /// Shared with the window so it creates its CME swapchains for the current connection
+    pub connection: Arc<Mutex<Connection>>,
+    pub projection: Projection,
+    pub zoom: Arc<Mutex<Zoom>>,
+    pub view_layout: ViewLayout,
+    /// Set with `--left-res` and `--right-res`, every cell is then one eye of the primary camera
+    /// with a CME image of its own
+    pub per_eye: bool,
+    /// Moves every view relative to its camera, see [`ViewLayout::views`]
+    pub view_offset: Vec3,
+    pub model: ModelResource,
+    /// Name of the part of `model` showing the rendered image
+    pub model_part: String,
+    /// Set on `model_part` after `unlit` whenever a preview is created, from the config's
+    /// `[material]` table
+    pub material: BTreeMap<String, MaterialValue>,
+    /// Set with `--watch-model`, the previews are recreated when it reports a change
+    pub model_watcher: Option<ModelWatcher>,
+    /// Whether to show `model` at all, without it the frames only go to the camera
+    pub preview: bool,
+    /// Passed on to reconnects, see [`Connection::connect`]
+    pub hdr: bool,
+    pub max_fps: Option<u32>,
+    /// Warn when no frame arrived for this long
+    pub frame_timeout: Option<Duration>,
+    /// Also reconnect once `frame_timeout` passed
+    pub reconnect_on_stall: bool,
+    pub export: Option<ExportSocket>,
+    /// Set with `--bench`, shuts down once enough frames were measured
+    pub bench: Option<Bench>,
+    pub fly_cameras: Arc<Mutex<FlyCameras>>,
+    /// Set with `--gamepad`, moves the primary camera and zooms
+    pub gamepad: Option<Gamepad>,
+    /// Drives the primary camera while set
+    pub camera_path: Option<CameraPath>,
+    pub screenshot: Arc<AtomicBool>,
+    /// Set when an exposure bracket was requested, the stardust loop captures it over the next
+    /// frames
+    pub bracket: Arc<AtomicBool>,
+    pub bracket_count: u32,
+    /// Exposure difference between the bracket's frames in stops
+    pub bracket_stops: f32,
+    /// The base exposure brackets are spread around
+    pub color_correction: Option<ColorCorrection>,
+    /// While set frames are skipped, holding the last one in the window
+    pub paused: Arc<AtomicBool>,
+    /// Set while the window is unfocused with `--pause-on-blur`
+    pub blurred: Arc<AtomicBool>,
+    /// Frame rate while [`Self::blurred`], `None` skips every frame like [`Self::paused`]
+    pub blur_fps: Option<u32>,
+    /// Set with `--skip-policy`, whether frames are skipped while the GPU is still busy
+    pub skip_policy: SkipPolicy,
+    /// Toggled with F2, outlines every camera's frustum in the scene
+    pub show_frustum: Arc<AtomicBool>,
+    /// Set on F3, the stardust loop takes it and logs the matrices of the next frame
+    pub dump_matrices: Arc<AtomicBool>,
+    /// Toggled with F5, whether the preview panels ignore the scene's lighting
+    pub unlit: Arc<AtomicBool>,
+    /// Logs the dmatex timeline points of every submit with `--log-timeline`
+    pub log_timeline: bool,
+    /// Read by the status endpoint
+    pub status: Arc<Mutex<Status>>,
+    /// Shuts down once reached, set with `--duration`
+    pub deadline: Option<Instant>,
+    pub shutdown: CancellationToken,
+}
+impl StardustLoop {
+    /// A loop with every optional feature off, callers turn on what they need through the
+    /// fields.
+    pub fn new(
+        renderer: Renderer,
+        target: Target,
+        instance: Arc<Instance>,
+        connection: Connection,
+        projection: Projection,
+        fly_cameras: FlyCameras,
+    ) -> Self {
+        Self {
+            renderer,
+            target,
+            instance,
+            connection: Arc::new(Mutex::new(connection)),
+            zoom: Arc::new(Mutex::new(Zoom::new(&projection))),
+            projection,
+            view_layout: ViewLayout::Mono,
+            per_eye: false,
+            view_offset: Vec3::ZERO,
+            model: ModelResource {
+                namespace: "vk".to_owned(),
+                name: "panel".to_owned(),
+            },
+            model_part: "Panel".to_owned(),
+            material: BTreeMap::new(),
+            model_watcher: None,
+            preview: true,
+            hdr: false,
+            max_fps: None,
+            frame_timeout: None,
+            reconnect_on_stall: false,
+            export: None,
+            bench: None,
+            fly_cameras: Arc::new(Mutex::new(fly_cameras)),
+            gamepad: None,
+            camera_path: None,
+            screenshot: Arc::default(),
+            bracket: Arc::default(),
+            bracket_count: 3,
+            bracket_stops: 1.0,
+            color_correction: None,
+            paused: Arc::default(),
+            blurred: Arc::default(),
+            blur_fps: None,
+            skip_policy: SkipPolicy::Queue,
+            show_frustum: Arc::default(),
+            dump_matrices: Arc::default(),
+            unlit: Arc::new(AtomicBool::new(true)),
+            log_timeline: false,
+            status: Arc::default(),
+            deadline: None,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Runs until shutdown, reconnecting whenever the server goes away. Only fails if the model
+    /// can't be set up, which would fail again on every reconnect.
+    /// Prints the session's frame time summary once done.
+    pub async fn run(mut self, mut async_loop: AsyncEventLoop) -> Result<(), SetupError> {
+        let mut session_stats = SessionStats::default();
+        loop {
+            let connection = self.connection.lock().unwrap().clone();
+            let exit = self
+                .run_connected(
+                    &connection,
+                    async_loop.get_event_handle(),
+                    &mut session_stats,
+                )
+                .await;
+            match exit {
+                Err(err) => {
+                    error!("{err}");
+                    self.shutdown.cancel();
+                    session_stats.print_summary();
+                    return Err(err);
+                }
+                Ok(LoopExit::Shutdown) => break,
+                Ok(LoopExit::Disconnected) => self.status.lock().unwrap().connected = false,
+            }
+            drop(connection);
+            let Some(new_loop) = self.reconnect().await else {
+                break;
+            };
+            async_loop = new_loop;
+        }
+        info!("stardust loop shutting down");
+        session_stats.print_summary();
+        Ok(())
+    }
+
+    async fn run_connected(
+        &mut self,
+        connection: &Connection,
+        event: AsyncEventHandle,
+        session_stats: &mut SessionStats,
+    ) -> Result<LoopExit, SetupError> {
+        let client = &connection.client;
+        let transforms = self.cell_transforms(&self.fly_cameras.lock().unwrap());
+        let mut cells = match transforms
+            .into_iter()
+            .map(|transform| self.create_cell(connection, transform))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(cells) => cells,
+            Err(err @ SetupError::Camera(_)) => {
+                warn!("lost connection to the server: {err}");
+                return Ok(LoopExit::Disconnected);
+            }
+            Err(err) => return Err(err),
+        };
+        self.status.lock().unwrap().connected = true;
+
+        let mut stats = FrameStats::default();
+        let mut limiter = self.max_fps.map(FrameLimiter::new);
+        let mut blur_limiter = self.blur_fps.map(FrameLimiter::new);
+        let watchdog = self.frame_timeout.map(Watchdog::spawn);
+        let mut bracket: Option<Bracket> = None;
+        // the window image is undefined until the server asks for the first frame
+        let mut no_signal = true;
+        self.show_status("NO SIGNAL");
+        loop {
+            let held = if self.blurred.load(Ordering::Relaxed) {
+                &blur_limiter
+            } else {
+                &limiter
+            }
+            .as_ref()
+            .and_then(FrameLimiter::deadline);
+            let mut released = false;
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return Ok(LoopExit::Shutdown),
+                _ = tokio::time::sleep_until(held.unwrap_or_else(Instant::now).into()),
+                    if held.is_some() =>
+                {
+                    released = true;
+                }
+                _ = tokio::time::sleep_until(self.deadline.unwrap_or_else(Instant::now).into()),
+                    if self.deadline.is_some() =>
+                {
+                    info!("the requested duration passed, shutting down");
+                    self.shutdown.cancel();
+                    return Ok(LoopExit::Shutdown);
+                }
+                _ = watchdog::stalled(watchdog.as_ref()), if self.reconnect_on_stall => {
+                    warn!("reconnecting to the stalled server");
+                    return Ok(LoopExit::Disconnected);
+                }
+                timeout = tokio::time::timeout(EVENT_TIMEOUT, event.wait())
+                    .instrument(trace_span!("wait_event")) =>
+                {
+                    if timeout.is_err() {
+                        let transform = self.fly_cameras.lock().unwrap().cameras[0].transform();
+                        if let Err(err) = cells[0].camera.set_local_transform(transform) {
+                            warn!("lost connection to the server: {err}");
+                            return Ok(LoopExit::Disconnected);
+                        }
+                        if no_signal {
+                            self.show_status("NO SIGNAL");
+                        }
+                        continue;
+                    }
+                }
+            }
+            if self.shutdown.is_cancelled() {
+                return Ok(LoopExit::Shutdown);
+            }
+            // there's no await until the next iteration, so the guard doesn't cross one
+            let _frame_span = trace_span!("frame").entered();
+            let delta = if released {
+                // the event behind the held frame was already taken, so none is read here
+                let held = if self.blurred.load(Ordering::Relaxed) {
+                    &mut blur_limiter
+                } else {
+                    &mut limiter
+                };
+                match held.as_mut().and_then(FrameLimiter::release) {
+                    Some(_) if self.paused.load(Ordering::Relaxed) => continue,
+                    Some(delta) => delta,
+                    None => continue,
+                }
+            } else {
+                let frame_info = match client.get_root().recv_root_event() {
+                    Some(RootEvent::Ping { response }) => {
+                        response.send_ok(());
+                        continue;
+                    }
+                    Some(RootEvent::SaveState { response }) => {
+                        let state = SavedState::new(
+                            self.fly_cameras.lock().unwrap().primary(),
+                            self.projection.fov_y.to_degrees(),
+                        );
+                        match ClientState::from_data_root(Some(state), client.get_root()) {
+                            Ok(state) => response.send_ok(state),
+                            Err(err) => error!("failed to save state: {err}"),
+                        }
+                        continue;
+                    }
+                    None => {
+                        continue;
+                    }
+                    Some(RootEvent::Frame { info }) => info,
+                };
+                session_stats.add(frame_info.delta);
+                if let Some(watchdog) = &watchdog {
+                    watchdog.frame_delivered();
+                }
+                if self.paused.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let limiter = if self.blurred.load(Ordering::Relaxed) {
+                    match &mut blur_limiter {
+                        Some(blur_limiter) => Some(blur_limiter),
+                        None => continue,
+                    }
+                } else {
+                    limiter.as_mut()
+                };
+                match limiter {
+                    // held frames are rendered once their deadline passes
+                    Some(limiter) => match limiter.accept(frame_info.delta) {
+                        Some(delta) => delta,
+                        None => continue,
+                    },
+                    None => frame_info.delta,
+                }
+            };
+            stats.frame_delivered();
+            let gamepad_input = self.gamepad.as_ref().map(Gamepad::input);
+            if let Some(input) = &gamepad_input {
+                self.zoom.lock().unwrap().handle_gamepad(input.zoom, delta);
+            }
+            let zoom = *self.zoom.lock().unwrap();
+            zoom.update(&mut self.projection, delta);
+            if self
+                .model_watcher
+                .as_ref()
+                .is_some_and(ModelWatcher::take_changed)
+            {
+                self.reload_previews(&mut cells);
+            }
+            let dump_matrices = self.dump_matrices.swap(false, Ordering::Relaxed);
+            let (transforms, poses) = {
+                let mut fly_cameras = self.fly_cameras.lock().unwrap();
+                fly_cameras.update(delta);
+                if let Some(input) = &gamepad_input {
+                    fly_cameras.primary_mut().apply_gamepad(input, delta);
+                }
+                if let Some(camera_path) = &mut self.camera_path {
+                    camera_path.apply(delta, fly_cameras.primary_mut());
+                }
+                let poses = dump_matrices.then(|| self.cell_poses(&fly_cameras));
+                (self.cell_transforms(&fly_cameras), poses)
+            };
+            // before the frame is drawn, so it shows the pose set by input, paths or a
+            // CameraControl this frame
+            for (cell, transform) in cells.iter().zip(transforms) {
+                if let Err(err) = cell.camera.set_local_transform(transform) {
+                    warn!("lost connection to the server: {err}");
+                    return Ok(LoopExit::Disconnected);
+                }
+            }
+
+            if self.bracket.swap(false, Ordering::Relaxed) && bracket.is_none() {
+                bracket = Some(Bracket::new(
+                    self.bracket_count,
+                    self.bracket_stops,
+                    self.color_correction.unwrap_or_default(),
+                ));
+            }
+            // the cameras moved on anyway, so the next rendered frame shows the newest state
+            if self.skip_policy == SkipPolicy::Latest && self.renderer.is_busy() {
+                debug!("the last frame is still rendering, skipping this one");
+                continue;
+            }
+            let submit_start = Instant::now();
+            let overlay_text = format!(
+                "{} FOV {:.0}",
+                stats.summary(),
+                self.projection.fov_y.to_degrees()
+            );
+            let rendered = self.target.render(
+                &mut self.renderer,
+                connection,
+                cells.len(),
+                &self.screenshot,
+                bracket.as_ref().and_then(Bracket::shot),
+                overlay_text,
+            )?;
+            let timings = self.renderer.timings.as_mut().map(mem::take);
+            let rendered = match rendered {
+                Rendered::Cells(rendered) => rendered,
+                Rendered::Skipped => continue,
+                Rendered::Finished => {
+                    info!("headless output finished");
+                    self.shutdown.cancel();
+                    return Ok(LoopExit::Shutdown);
+                }
+            };
+            if rendered.iter().all(Option::is_none) {
+                continue;
+            }
+            no_signal = false;
+            // only the first cell is read back
+            if let Some(current) = &mut bracket
+                && rendered[0].is_some()
+            {
+                current.advance();
+                if current.is_done() {
+                    bracket = None;
+                }
+            }
+            stats.frame_submitted(submit_start.elapsed());
+            if let (Some(bench), Some(timings)) = (&mut self.bench, timings) {
+                bench.record(timings);
+            }
+            let resolution = rendered
+                .iter()
+                .flatten()
+                .next()
+                .map(|(_, res)| [res[0] * self.cell_views(), res[1]]);
+            {
+                let mut status = self.status.lock().unwrap();
+                status.fps = stats.fps();
+                status.frames += 1;
+                status.resolution = resolution;
+            }
+            if let Some(resolution) = resolution {
+                self.target.update_title_stats(stats.fps(), resolution);
+            }
+
+            let show_frustum = self.show_frustum.load(Ordering::Relaxed);
+            let unlit = self.unlit.load(Ordering::Relaxed);
+            let jitter = self.target.subframe().map(accumulate::jitter);
+            let cme_format = self.target.cme_format(connection);
+            for (index, (cell, rendered)) in cells.iter_mut().zip(rendered).enumerate() {
+                let Some((submit_info, res)) = rendered else {
+                    continue;
+                };
+                let ratio = res[0] as f32 / res[1] as f32;
+                let mat = match jitter {
+                    Some(jitter) => self.projection.jittered(jitter, [res[0], res[1]]),
+                    None => self.projection,
+                }
+                .matrix(ratio);
+                if let Some(poses) = &poses {
+                    log_matrices(index, mat, poses[index], self.view_offset);
+                }
+                if let Err(err) = cell.update_frustum(show_frustum, &self.projection, ratio) {
+                    warn!("lost connection to the server: {err}");
+                    return Ok(LoopExit::Disconnected);
+                }
+                if let Err(err) = cell.update_unlit(unlit) {
+                    warn!("lost connection to the server: {err}");
+                    return Ok(LoopExit::Disconnected);
+                }
+                let views = if self.per_eye {
+                    self.view_layout.eye_view(index, mat, self.view_offset)
+                } else {
+                    self.view_layout.views(mat, self.view_offset)
+                };
+                if self.log_timeline {
+                    info!(
+                        cell = index,
+                        dmatex_id = ?submit_info.dmatex_id,
+                        acquire_point = ?submit_info.acquire_point,
+                        release_point = ?submit_info.release_point,
+                        "submitted to the dmatex timeline"
+                    );
+                    if cell.preview.is_some() {
+                        let panel = panel_submit_info(&submit_info);
+                        info!(
+                            cell = index,
+                            acquire_point = ?panel.acquire_point,
+                            release_point = ?panel.release_point,
+                            "the panel samples the dmatex"
+                        );
+                    }
+                }
+                // the submit takes it, exported only once the server has it
+                let exported = self.export.is_some().then(|| DmatexSubmitInfo {
+                    dmatex_id: submit_info.dmatex_id,
+                    acquire_point: submit_info.acquire_point,
+                    release_point: submit_info.release_point,
+                });
+                let submitted = trace_span!("request_draw", cell = index)
+                    .in_scope(|| cell.submit(submit_info, &views));
+                if let Err(err) = submitted {
+                    warn!("lost connection to the server: {err}");
+                    return Ok(LoopExit::Disconnected);
+                }
+                if let Some(export) = &mut self.export
+                    && let Some(exported) = &exported
+                {
+                    export.publish(index, exported, res, self.cell_views(), cme_format);
+                }
+            }
+            if let Some(bench) = &self.bench
+                && bench.is_done()
+            {
+                if let Err(err) = bench.write() {
+                    error!("failed to write the benchmark results: {err}");
+                }
+                self.shutdown.cancel();
+                return Ok(LoopExit::Shutdown);
+            }
+        }
+    }
+
+    /// The pose of every cell's camera, the eyes share the primary camera with `per_eye`.
+    fn cell_transforms(&self, fly_cameras: &FlyCameras) -> Vec<Transform> {
+        if self.per_eye {
+            let transform = fly_cameras.primary().transform();
+            vec![transform.clone(), transform]
+        } else {
+            fly_cameras
+                .cameras
+                .iter()
+                .map(FlyCamera::transform)
+                .collect()
+        }
+    }
+
+    /// Like [`Self::cell_transforms`] but as matrices relative to the client root.
+    fn cell_poses(&self, fly_cameras: &FlyCameras) -> Vec<Mat4> {
+        if self.per_eye {
+            vec![fly_cameras.primary().matrix(); 2]
+        } else {
+            fly_cameras.cameras.iter().map(FlyCamera::matrix).collect()
+        }
+    }
+
+    /// Number of views in each cell's CME image.
+    fn cell_views(&self) -> u32 {
+        if self.per_eye {
+            1
+        } else {
+            self.view_layout.count()
+        }
+    }
+
+    fn create_cell(
+        &self,
+        connection: &Connection,
+        transform: Transform,
+    ) -> Result<CameraCell, SetupError> {
+        let camera = Camera::create(connection.client.get_root(), transform)
+            .map_err(|err| SetupError::Camera(err.into()))?;
+        let preview = self
+            .preview
+            .then(|| self.create_preview(&camera))
+            .transpose()?;
+        Ok(CameraCell {
+            preview,
+            camera,
+            frustum: None,
+        })
+    }
+
+    fn create_preview(&self, camera: &Camera) -> Result<Preview, SetupError> {
+        let model = Model::create(
+            camera,
+            Transform::from_scale([0.2; 3]),
+            &self.model.resource_id(),
+        )
+        .map_err(|err| SetupError::Model {
+            model: self.model.to_string(),
+            source: err.into(),
+        })?;
+        let unlit = self.unlit.load(Ordering::Relaxed);
+        let panel = model
+            .part(&self.model_part)
+            .and_then(|panel| {
+                panel.set_material_parameter("unlit", MaterialParameter::Bool(unlit))?;
+                for (name, value) in &self.material {
+                    panel.set_material_parameter(name, value.parameter())?;
+                }
+                Ok(panel)
+            })
+            .map_err(|err| SetupError::NoSuchModelPart {
+                model: self.model.to_string(),
+                part: self.model_part.clone(),
+                source: err.into(),
+            })?;
+        Ok(Preview {
+            panel,
+            unlit,
+            _model: model,
+        })
+    }
+
+    /// Recreates the previews after the model file changed, the `diffuse` image is set again
+    /// with the next submit. A cell keeps its old preview if the new model fails to load.
+    fn reload_previews(&self, cells: &mut [CameraCell]) {
+        info!(model = %self.model, "the model changed, reloading it");
+        for cell in cells.iter_mut().filter(|cell| cell.preview.is_some()) {
+            match self.create_preview(&cell.camera) {
+                Ok(preview) => cell.preview = Some(preview),
+                Err(err) => warn!("failed to reload the model, keeping the old one: {err}"),
+            }
+        }
+    }
+
+    /// Retries connecting with exponential backoff, showing a reconnecting status in the window
+    /// meanwhile. Returns `None` on shutdown.
+    async fn reconnect(&mut self) -> Option<AsyncEventLoop> {
+        let mut delay = MIN_RECONNECT_DELAY;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let deadline = Instant::now() + delay;
+            while Instant::now() < deadline {
+                self.show_status(&format!("RECONNECTING - ATTEMPT {attempt}"));
+                tokio::select! {
+                    _ = self.shutdown.cancelled() => return None,
+                    _ = tokio::time::sleep(STATUS_INTERVAL) => {}
+                }
+            }
+            info!("trying to reconnect");
+            match Connection::connect(self.hdr).await {
+                Ok((connection, async_loop, _)) => {
+                    let same_device = connection
+                        .render_dev
+                        .get_physical_device(&self.instance)
+                        .is_some_and(|dev| {
+                            dev.handle() == self.renderer.dev.physical_device().handle()
+                        });
+                    if !same_device {
+                        error!("the server now renders on a different gpu, can't reconnect");
+                        self.shutdown.cancel();
+                        return None;
+                    }
+                    if let Err(err) = self.reconnect_target(&connection) {
+                        error!("failed to recreate the CME swapchain: {err}");
+                    } else {
+                        info!("reconnected");
+                        *self.connection.lock().unwrap() = connection;
+                        return Some(async_loop);
+                    }
+                }
+                Err(err) => warn!("failed to reconnect: {err}"),
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    fn reconnect_target(&mut self, connection: &Connection) -> Result<(), SetupError> {
+        self.renderer.wait_in_flight()?;
+        match &mut self.target {
+            Target::Window(output) => match output.lock().unwrap().as_mut() {
+                Some(output) => output.reconnect(connection, &self.renderer.dev),
+                None => Ok(()),
+            },
+            Target::Headless(sink) => sink.reconnect(connection, &self.renderer.dev),
+        }
+    }
+
+    /// Shows `status` over the test pattern in the window, headless there's nothing to show.
+    fn show_status(&mut self, status: &str) {
+        if let Target::Window(output) = &self.target
+            && let Some(output) = output.lock().unwrap().as_mut()
+            && output.has_area()
+        {
+            if output.recreate {
+                if let Err(err) = output.recreate_window_swapchain() {
+                    warn!("failed to recreate the window swapchain: {err}");
+                    return;
+                }
+                output.recreate = false;
+            }
+            output.overlay.set_text(status);
+            output.set_cell(None);
+            if let Err(err) = self.renderer.render_status(output) {
+                warn!("failed to show the status: {err}");
+            }
+        }
+    }
+}
+
+/// Logs a frame that failed so the loop skips it, only a lost device ends the loop.
+fn skip_failed<T>(result: Result<T, FrameError>) -> Result<Option<T>, SetupError> {
+    match result {
+        Ok(rendered) => Ok(Some(rendered)),
+        Err(FrameError::DeviceLost) => Err(SetupError::DeviceLost),
+        Err(err) => {
+            warn!("skipping a frame: {err}");
+            Ok(None)
+        }
+    }
+}
+
+/// Logs what a cell's camera is drawn with for F3, to check the reverse-Z projection and the
+/// pose against what the server renders. The view-projection includes `--view-offset` but not
+/// the per eye offsets of stereo views.
+fn log_matrices(cell: usize, projection: Mat4, camera: Mat4, view_offset: Vec3) {
+    let view_projection = projection * (camera * Mat4::from_translation(view_offset)).inverse();
+    info!(cell, %projection, %camera, %view_projection, "camera matrices");
+}
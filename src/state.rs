@@ -0,0 +1,28 @@
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::fly_camera::FlyCamera;
+
+/// What gets handed to the server on `RootEvent::SaveState` and restored on the next launch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedState {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+}
+impl SavedState {
+    pub fn new(fly_camera: &FlyCamera, fov: f32) -> Self {
+        Self {
+            position: fly_camera.position.into(),
+            yaw: fly_camera.yaw,
+            pitch: fly_camera.pitch,
+            fov,
+        }
+    }
+
+    pub fn fly_camera(&self) -> FlyCamera {
+        FlyCamera::new(Vec3::from(self.position), self.yaw, self.pitch)
+    }
+}
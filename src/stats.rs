@@ -0,0 +1,151 @@
+use std::time::{Duration, Instant};
+
+/// Weight of the newest sample in the exponential moving averages.
+const SMOOTHING: f64 = 0.1;
+
+/// Smoothed timings of the stardust loop.
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    last_frame: Option<Instant>,
+    /// Wall-clock time between `RootEvent::Frame` deliveries
+    frame_time: Option<Duration>,
+    /// Time spent blitting and submitting a frame
+    submit_time: Option<Duration>,
+}
+impl FrameStats {
+    /// Call whenever a `RootEvent::Frame` arrives.
+    pub fn frame_delivered(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            smooth(&mut self.frame_time, now - last_frame);
+        }
+    }
+
+    pub fn frame_submitted(&mut self, submit_time: Duration) {
+        smooth(&mut self.submit_time, submit_time);
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.frame_time
+            .map_or(0.0, |frame_time| 1.0 / frame_time.as_secs_f64())
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time.unwrap_or_default()
+    }
+
+    pub fn submit_time(&self) -> Duration {
+        self.submit_time.unwrap_or_default()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "FPS {:.1} FRAME {:.2}MS BLIT {:.2}MS",
+            self.fps(),
+            self.frame_time().as_secs_f64() * 1000.0,
+            self.submit_time().as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+fn smooth(average: &mut Option<Duration>, sample: Duration) {
+    *average = Some(match *average {
+        Some(average) => average.mul_f64(1.0 - SMOOTHING) + sample.mul_f64(SMOOTHING),
+        None => sample,
+    });
+}
+
+/// Width of a histogram bucket in milliseconds
+const BUCKET_MS: f64 = 0.1;
+/// Frame times from 0 to 100ms get their own bucket, slower ones share the last
+const BUCKETS: usize = 1000;
+/// Buckets merged into one row of the printed histogram
+const BUCKETS_PER_ROW: usize = 10;
+const BAR_WIDTH: usize = 40;
+
+/// Frame times over the whole session, from the deltas of `RootEvent::Frame`, for comparing
+/// present modes and resolutions. Kept as a histogram so long sessions don't grow it.
+#[derive(Debug)]
+pub struct SessionStats {
+    buckets: Vec<u64>,
+    count: u64,
+    total_ms: f64,
+    min_ms: f64,
+    max_ms: f64,
+}
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS + 1],
+            count: 0,
+            total_ms: 0.0,
+            min_ms: f64::INFINITY,
+            max_ms: 0.0,
+        }
+    }
+}
+impl SessionStats {
+    /// `delta` is in seconds like the frame info's.
+    pub fn add(&mut self, delta: f32) {
+        let ms = delta as f64 * 1000.0;
+        if ms.is_nan() || ms <= 0.0 {
+            return;
+        }
+        let bucket = ((ms / BUCKET_MS) as usize).min(BUCKETS);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_ms += ms;
+        self.min_ms = self.min_ms.min(ms);
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    /// Upper edge of the bucket containing the `quantile`th frame time, clamped to the maximum.
+    fn quantile_ms(&self, quantile: f64) -> f64 {
+        let target = (self.count as f64 * quantile).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return ((bucket + 1) as f64 * BUCKET_MS).min(self.max_ms);
+            }
+        }
+        self.max_ms
+    }
+
+    /// Prints the frame time summary and a histogram in 1ms rows to stdout.
+    pub fn print_summary(&self) {
+        if self.count == 0 {
+            return;
+        }
+        println!(
+            "{} frames, frame time min {:.2}ms avg {:.2}ms p99 {:.2}ms max {:.2}ms",
+            self.count,
+            self.min_ms,
+            self.total_ms / self.count as f64,
+            self.quantile_ms(0.99),
+            self.max_ms,
+        );
+        let rows: Vec<u64> = self
+            .buckets
+            .chunks(BUCKETS_PER_ROW)
+            .map(|row| row.iter().sum())
+            .collect();
+        let largest = rows.iter().copied().max().unwrap_or(1);
+        let row_ms = BUCKET_MS * BUCKETS_PER_ROW as f64;
+        for (row, &count) in rows.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let bar = "#".repeat((count * BAR_WIDTH as u64).div_ceil(largest) as usize);
+            let start = row as f64 * row_ms;
+            if row * BUCKETS_PER_ROW >= BUCKETS {
+                println!("{:>11} {count:>8} {bar}", format!(">={start:.0}ms"));
+            } else {
+                println!(
+                    "{:>11} {count:>8} {bar}",
+                    format!("{start:.0}-{:.0}ms", start + row_ms)
+                );
+            }
+        }
+    }
+}
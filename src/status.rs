@@ -0,0 +1,72 @@
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::error::SetupError;
+
+/// Health of the client as reported by the status endpoint, kept up to date by the stardust loop.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Status {
+    pub connected: bool,
+    /// Smoothed rate of frames delivered by the server
+    pub fps: f64,
+    /// Frames submitted since startup
+    pub frames: u64,
+    /// Size of the last submitted CME image, all views side by side
+    pub resolution: Option<[u32; 2]>,
+}
+
+/// Answers every HTTP request with the current [`Status`] as JSON.
+pub struct StatusServer {
+    listener: TcpListener,
+}
+impl StatusServer {
+    /// Only listens on localhost.
+    pub async fn bind(port: u16) -> Result<Self, SetupError> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .map_err(|err| SetupError::StatusServer(port, err))?;
+        info!(port, "serving status");
+        Ok(Self { listener })
+    }
+
+    pub async fn run(self, status: Arc<Mutex<Status>>, shutdown: CancellationToken) {
+        loop {
+            let stream = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        warn!("failed to accept a status connection: {err}");
+                        continue;
+                    }
+                },
+            };
+            let body = serde_json::to_string(&*status.lock().unwrap()).unwrap();
+            tokio::spawn(async move {
+                if let Err(err) = respond(stream, &body).await {
+                    warn!("failed to answer a status request: {err}");
+                }
+            });
+        }
+    }
+}
+
+/// The request itself doesn't matter, every path gets the status.
+async fn respond(mut stream: TcpStream, body: &str) -> std::io::Result<()> {
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
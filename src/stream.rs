@@ -0,0 +1,80 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use tracing::{info, warn};
+use winit::dpi::PhysicalSize;
+
+use crate::error::SetupError;
+
+/// Used for vaapi codecs when the gpu doesn't report its own render node, the first gpu's.
+pub const FALLBACK_VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// Encodes NV12 frames to H.264 and sends them over RTP by piping them into ffmpeg. The frames
+/// take a roundtrip through host memory, but the conversion to NV12 already happened on the gpu.
+pub struct Encoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+impl Encoder {
+    /// `url` is `udp://host:port`, the SDP viewers need is written to `sdp_path`. `size` is the
+    /// size of a whole frame with all views side by side. `vaapi_device` is only used by vaapi
+    /// codecs.
+    pub fn spawn(
+        url: &str,
+        codec: &str,
+        vaapi_device: &Path,
+        size: PhysicalSize<u32>,
+        sdp_path: &Path,
+    ) -> Result<Self, SetupError> {
+        let target = url
+            .strip_prefix("udp://")
+            .ok_or_else(|| SetupError::StreamUrl(url.to_string()))?;
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-hide_banner", "-loglevel", "warning"])
+            // frames arrive whenever the server delivers them
+            .args(["-use_wallclock_as_timestamps", "1"])
+            .args(["-f", "rawvideo", "-pix_fmt", "nv12"])
+            .args(["-video_size", &format!("{}x{}", size.width, size.height)])
+            .args(["-i", "-"]);
+        if codec.ends_with("_vaapi") {
+            command
+                .arg("-vaapi_device")
+                .arg(vaapi_device)
+                .args(["-vf", "format=nv12,hwupload"]);
+        }
+        command
+            .args(["-c:v", codec, "-bf", "0"])
+            .arg("-sdp_file")
+            .arg(sdp_path)
+            .args(["-f", "rtp", &format!("rtp://{target}")])
+            .stdin(Stdio::piped());
+        let mut child = command.spawn().map_err(SetupError::Encoder)?;
+        info!(url, codec, vaapi_device = %vaapi_device.display(), sdp = %sdp_path.display(), "streaming");
+        Ok(Self {
+            stdin: child.stdin.take(),
+            child,
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        match &mut self.stdin {
+            Some(stdin) => stdin.write_all(frame),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+impl Drop for Encoder {
+    /// Closing stdin makes ffmpeg flush and exit.
+    fn drop(&mut self) {
+        drop(self.stdin.take());
+        match self.child.wait() {
+            Ok(status) if !status.success() => warn!(%status, "ffmpeg exited with an error"),
+            Ok(_) => {}
+            Err(err) => warn!("failed to wait for ffmpeg: {err}"),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, BlitImageInfo, CopyBufferToImageInfo, ImageBlit,
+        PrimaryAutoCommandBuffer,
+    },
+    format::Format,
+    image::{Image, ImageCreateInfo, ImageType, ImageUsage, sampler::Filter},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::error::{FrameContext, FrameError, SetupContext, SetupError};
+
+/// 75% color bars from left to right, sRGB encoded so they look the same on every swapchain
+/// format.
+const BARS: [[u8; 4]; 7] = [
+    [191, 191, 191, 255],
+    [191, 191, 0, 255],
+    [0, 191, 191, 255],
+    [0, 191, 0, 255],
+    [191, 0, 191, 255],
+    [191, 0, 0, 255],
+    [0, 0, 191, 255],
+];
+
+/// Color bars filling the window while there's no CME image to show, e.g. before the first
+/// frame or while reconnecting. The bars are a single row image stretched over the window.
+pub struct TestPattern {
+    buffer: Subbuffer<[u8]>,
+    image: Arc<Image>,
+}
+impl TestPattern {
+    pub fn new(memalloc: &Arc<StandardMemoryAllocator>) -> Result<Self, SetupError> {
+        let buffer = Buffer::from_iter(
+            memalloc.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            BARS.into_iter().flatten(),
+        )
+        .vk_context("allocating the test pattern buffer")?;
+        let image = Image::new(
+            memalloc.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R8G8B8A8_SRGB,
+                extent: [BARS.len() as u32, 1, 1],
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .vk_context("allocating the test pattern image")?;
+        Ok(Self { buffer, image })
+    }
+
+    /// Records filling all of `dst` with the bars, anything drawn on top has to be recorded
+    /// after this.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dst: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
+                self.buffer.clone(),
+                self.image.clone(),
+            ))
+            .frame_context("uploading the test pattern")?;
+        let [width, height, _] = dst.extent();
+        builder
+            .blit_image(BlitImageInfo {
+                regions: [ImageBlit {
+                    src_subresource: self.image.subresource_layers(),
+                    src_offsets: [[0, 0, 0], self.image.extent()],
+                    dst_subresource: dst.subresource_layers(),
+                    dst_offsets: [[0, 0, 0], [width, height, 1]],
+                    ..Default::default()
+                }]
+                .into(),
+                // keeps the edges between the bars sharp
+                filter: Filter::Nearest,
+                ..BlitImageInfo::images(self.image.clone(), dst)
+            })
+            .frame_context("blitting the test pattern")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,466 @@
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{
+        AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo,
+        SubpassContents, SubpassEndInfo,
+    },
+    descriptor_set::{
+        DescriptorSet, WriteDescriptorSet, allocator::StandardDescriptorSetAllocator,
+    },
+    device::{Device, DeviceOwned},
+    format::Format,
+    image::{
+        Image, ImageCreateInfo, ImageSubresourceRange, ImageType, ImageUsage,
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+        graphics::{
+            GraphicsPipelineCreateInfo,
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    error::{FrameContext, FrameError, SetupContext, SetupError},
+    frame::{Flip, HDR_CME_FORMAT, Scaling, side_by_side_rects},
+};
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: r"
+            #version 450
+
+            layout(push_constant) uniform PushConstants {
+                vec2 uv_min;
+                vec2 uv_max;
+                uint layer;
+                uint tone_map;
+                float exposure;
+                float gamma;
+            } pc;
+
+            layout(location = 0) out vec2 uv;
+
+            // a single triangle covering the whole viewport
+            void main() {
+                vec2 pos = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+                uv = mix(pc.uv_min, pc.uv_max, pos);
+                gl_Position = vec4(pos * 2.0 - 1.0, 0.0, 1.0);
+            }
+        ",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: r"
+            #version 450
+
+            layout(push_constant) uniform PushConstants {
+                vec2 uv_min;
+                vec2 uv_max;
+                uint layer;
+                uint tone_map;
+                float exposure;
+                float gamma;
+            } pc;
+
+            layout(set = 0, binding = 0) uniform sampler2DArray image;
+
+            layout(location = 0) in vec2 uv;
+            layout(location = 0) out vec4 color;
+
+            // in linear space, SRGB target images do the encoding
+            void main() {
+                vec4 src = max(texture(image, vec3(uv, pc.layer)), 0.0);
+                vec3 rgb = src.rgb * pc.exposure;
+                if (pc.tone_map != 0) {
+                    // Reinhard
+                    rgb = rgb / (1.0 + rgb);
+                }
+                color = vec4(pow(rgb, vec3(1.0 / pc.gamma)), min(src.a, 1.0));
+            }
+        ",
+    }
+}
+
+/// `--exposure` and `--gamma`, applied to the linear colors before they are encoded.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorCorrection {
+    /// Multiplies the colors
+    pub exposure: f32,
+    /// Colors are raised to `1 / gamma`, above 1 brightens the midtones
+    pub gamma: f32,
+}
+impl Default for ColorCorrection {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+/// Draws a CME image through a fragment shader where a blit can only convert the format:
+/// tone mapping HDR images for the window and applying the [`ColorCorrection`]. Lays the views
+/// out side by side like [`crate::frame::side_by_side_blit`].
+pub struct ToneMapper {
+    render_pass: Arc<RenderPass>,
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    descriptor_alloc: Arc<StandardDescriptorSetAllocator>,
+    /// Whether HDR images are tone mapped, otherwise they stay HDR
+    tone_map: bool,
+    correction: Option<ColorCorrection>,
+}
+impl ToneMapper {
+    /// `format` is the format of the images drawn into.
+    pub fn new(
+        dev: &Arc<Device>,
+        format: Format,
+        tone_map: bool,
+        correction: Option<ColorCorrection>,
+    ) -> Result<Self, SetupError> {
+        let render_pass = vulkano::single_pass_renderpass!(
+            dev.clone(),
+            attachments: {
+                color: {
+                    format: format,
+                    samples: 1,
+                    // other grid cells may already have been drawn
+                    load_op: Load,
+                    store_op: Store,
+                },
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {},
+            },
+        )
+        .vk_context("creating the tone mapping render pass")?;
+        let stages = [
+            vs::load(dev.clone())
+                .vk_context("loading the tone mapping vertex shader")?
+                .entry_point("main")
+                .unwrap(),
+            fs::load(dev.clone())
+                .vk_context("loading the tone mapping fragment shader")?
+                .entry_point("main")
+                .unwrap(),
+        ]
+        .map(PipelineShaderStageCreateInfo::new);
+        let layout = PipelineLayout::new(
+            dev.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(dev.clone())
+                .vk_context("creating the tone mapping pipeline layout")?,
+        )
+        .vk_context("creating the tone mapping pipeline layout")?;
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let pipeline = GraphicsPipeline::new(
+            dev.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .vk_context("creating the tone mapping pipeline")?;
+        let sampler = Sampler::new(
+            dev.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .vk_context("creating the tone mapping sampler")?;
+        Ok(Self {
+            render_pass,
+            pipeline,
+            sampler,
+            descriptor_alloc: Arc::new(StandardDescriptorSetAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            tone_map,
+            correction,
+        })
+    }
+
+    /// Whether `src` needs this instead of a plain blit.
+    pub fn applies_to(&self, src: &Image) -> bool {
+        self.correction.is_some() || (self.tone_map && src.format() == HDR_CME_FORMAT)
+    }
+
+    /// Records drawing every array layer of `src` into its own horizontal slice of the
+    /// `[min, size]` region `dst_rect` of `dst`. Like with the blit, uncovered parts of the region
+    /// are left untouched.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        src: Arc<Image>,
+        dst: Arc<Image>,
+        dst_rect: [[u32; 2]; 2],
+        scaling: Scaling,
+        flip: Option<Flip>,
+    ) -> Result<(), FrameError> {
+        let [src_width, src_height, _] = src.extent();
+        let rects = side_by_side_rects(
+            [src_width, src_height],
+            src.array_layers(),
+            dst_rect,
+            scaling,
+            flip,
+        );
+        let dst_view =
+            ImageView::new_default(dst).frame_context("creating the tone mapping target view")?;
+        self.begin(builder, &src, dst_view)?;
+        for (layer, (src_offsets, dst_offsets)) in rects.into_iter().enumerate() {
+            let uv = |offset: [u32; 3]| {
+                [
+                    offset[0] as f32 / src_width as f32,
+                    offset[1] as f32 / src_height as f32,
+                ]
+            };
+            self.draw(
+                builder,
+                &src,
+                layer as u32,
+                [uv(src_offsets[0]), uv(src_offsets[1])],
+                Viewport {
+                    offset: [dst_offsets[0][0] as f32, dst_offsets[0][1] as f32],
+                    extent: [
+                        (dst_offsets[1][0] - dst_offsets[0][0]) as f32,
+                        (dst_offsets[1][1] - dst_offsets[0][1]) as f32,
+                    ],
+                    depth_range: 0.0..=1.0,
+                },
+            )?;
+        }
+        builder
+            .end_render_pass(SubpassEndInfo::default())
+            .frame_context("ending the tone mapping pass")?;
+        Ok(())
+    }
+
+    /// Records drawing every array layer of `src` into the same layer of `dst`, which has the
+    /// same extent and layer count, keeping the views apart for the readback.
+    pub fn record_layers(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        src: Arc<Image>,
+        dst: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        let [width, height, _] = src.extent();
+        for layer in 0..src.array_layers() {
+            let dst_view = ImageView::new(
+                dst.clone(),
+                ImageViewCreateInfo {
+                    view_type: ImageViewType::Dim2d,
+                    subresource_range: ImageSubresourceRange {
+                        array_layers: layer..layer + 1,
+                        ..dst.subresource_range()
+                    },
+                    ..ImageViewCreateInfo::from_image(&dst)
+                },
+            )
+            .frame_context("creating the tone mapping target view")?;
+            self.begin(builder, &src, dst_view)?;
+            self.draw(
+                builder,
+                &src,
+                layer,
+                [[0.0, 0.0], [1.0, 1.0]],
+                Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [width as f32, height as f32],
+                    depth_range: 0.0..=1.0,
+                },
+            )?;
+            builder
+                .end_render_pass(SubpassEndInfo::default())
+                .frame_context("ending the tone mapping pass")?;
+        }
+        Ok(())
+    }
+
+    /// Begins the render pass into `dst` with `src` bound.
+    fn begin(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        src: &Arc<Image>,
+        dst: Arc<ImageView>,
+    ) -> Result<(), FrameError> {
+        let src_view = ImageView::new(
+            src.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                ..ImageViewCreateInfo::from_image(src)
+            },
+        )
+        .frame_context("creating the tone mapping source view")?;
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_alloc.clone(),
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                src_view,
+                self.sampler.clone(),
+            )],
+            [],
+        )
+        .frame_context("creating the tone mapping descriptor set")?;
+        let framebuffer = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![dst],
+                ..Default::default()
+            },
+        )
+        .frame_context("creating the tone mapping framebuffer")?;
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .and_then(|builder| builder.bind_pipeline_graphics(self.pipeline.clone()))
+            .and_then(|builder| {
+                builder.bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.pipeline.layout().clone(),
+                    0,
+                    descriptor_set,
+                )
+            })
+            .frame_context("beginning the tone mapping pass")?;
+        Ok(())
+    }
+
+    /// Draws the `[min, max]` uv region of `layer` of `src` into `viewport`.
+    fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        src: &Image,
+        layer: u32,
+        [uv_min, uv_max]: [[f32; 2]; 2],
+        viewport: Viewport,
+    ) -> Result<(), FrameError> {
+        let correction = self.correction.unwrap_or_default();
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .and_then(|builder| {
+                builder.push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    vs::PushConstants {
+                        uv_min,
+                        uv_max,
+                        layer,
+                        tone_map: (self.tone_map && src.format() == HDR_CME_FORMAT) as u32,
+                        exposure: correction.exposure,
+                        gamma: correction.gamma,
+                    },
+                )
+            })
+            .frame_context("setting up the tone mapping draw")?;
+        unsafe { builder.draw(3, 1, 0, 0) }.frame_context("drawing the tone mapping pass")?;
+        Ok(())
+    }
+}
+
+/// A copy of a CME image with a [`ColorCorrection`] applied, in the same format and layout so it
+/// can be read back in its place. HDR images stay HDR.
+pub struct CorrectedImage {
+    tone_mapper: ToneMapper,
+    image: Arc<Image>,
+}
+impl CorrectedImage {
+    pub fn new(
+        memalloc: &Arc<StandardMemoryAllocator>,
+        size: PhysicalSize<u32>,
+        views: u32,
+        format: Format,
+        correction: ColorCorrection,
+    ) -> Result<Self, SetupError> {
+        let tone_mapper = ToneMapper::new(memalloc.device(), format, false, Some(correction))?;
+        let image = Image::new(
+            memalloc.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format,
+                extent: [size.width, size.height, 1],
+                array_layers: views,
+                usage: ImageUsage::COLOR_ATTACHMENT
+                    | ImageUsage::SAMPLED
+                    | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .vk_context("allocating the color corrected image")?;
+        Ok(Self { tone_mapper, image })
+    }
+
+    pub fn correction(&self) -> ColorCorrection {
+        self.tone_mapper.correction.unwrap_or_default()
+    }
+
+    /// Applies to the frames recorded from now on.
+    pub fn set_correction(&mut self, correction: ColorCorrection) {
+        self.tone_mapper.correction = Some(correction);
+    }
+
+    /// Whether this can take CME images of that size, layer count and format.
+    pub fn fits(&self, size: PhysicalSize<u32>, views: u32, format: Format) -> bool {
+        self.image.extent() == [size.width, size.height, 1]
+            && self.image.array_layers() == views
+            && self.image.format() == format
+    }
+
+    /// Records the corrected copy of `src`, returning the image it ends up in.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        src: Arc<Image>,
+    ) -> Result<Arc<Image>, FrameError> {
+        self.tone_mapper
+            .record_layers(builder, src, self.image.clone())?;
+        Ok(self.image.clone())
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use tracing::{debug, error, info, trace, warn};
+use vulkano::{
+    VulkanLibrary,
+    instance::{
+        Instance,
+        debug::{
+            DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger,
+            DebugUtilsMessengerCallback, DebugUtilsMessengerCreateInfo,
+        },
+    },
+};
+
+use crate::error::{SetupContext, SetupError};
+
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Whether both the validation layer and the debug utils extension are installed, warning if not.
+pub fn is_available(library: &VulkanLibrary) -> bool {
+    let has_layer = match library.layer_properties() {
+        Ok(mut layers) => layers.any(|layer| layer.name() == VALIDATION_LAYER),
+        Err(err) => {
+            warn!("failed to query the vulkan layers: {err}");
+            false
+        }
+    };
+    if !has_layer {
+        warn!("{VALIDATION_LAYER} is not installed, running without validation");
+        return false;
+    }
+    if !library.supported_extensions().ext_debug_utils {
+        warn!("VK_EXT_debug_utils is not supported, running without validation");
+        return false;
+    }
+    true
+}
+
+/// Routes validation messages into tracing, messages stop once the returned messenger is dropped.
+pub fn create_messenger(instance: &Arc<Instance>) -> Result<DebugUtilsMessenger, SetupError> {
+    // SAFETY: the callback doesn't call into vulkan
+    let callback = unsafe {
+        DebugUtilsMessengerCallback::new(|severity, ty, data| {
+            let id = data.message_id_name.unwrap_or("");
+            let message = data.message;
+            if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+                error!(target: "vulkan", ?ty, id, "{message}");
+            } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+                warn!(target: "vulkan", ?ty, id, "{message}");
+            } else if severity.intersects(DebugUtilsMessageSeverity::INFO) {
+                debug!(target: "vulkan", ?ty, id, "{message}");
+            } else {
+                trace!(target: "vulkan", ?ty, id, "{message}");
+            }
+        })
+    };
+    let messenger = DebugUtilsMessenger::new(
+        instance.clone(),
+        DebugUtilsMessengerCreateInfo {
+            message_severity: DebugUtilsMessageSeverity::ERROR
+                | DebugUtilsMessageSeverity::WARNING
+                | DebugUtilsMessageSeverity::INFO
+                | DebugUtilsMessageSeverity::VERBOSE,
+            message_type: DebugUtilsMessageType::GENERAL
+                | DebugUtilsMessageType::VALIDATION
+                | DebugUtilsMessageType::PERFORMANCE,
+            ..DebugUtilsMessengerCreateInfo::user_callback(callback)
+        },
+    )
+    .vk_context("creating the debug messenger")?;
+    info!("vulkan validation enabled");
+    Ok(messenger)
+}
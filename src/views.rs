@@ -0,0 +1,55 @@
+use glam::{Mat4, Vec3};
+use stardust_xr_fusion::{camera::View, spatial::Transform};
+
+/// How many views the camera renders and where they sit relative to the camera.
+#[derive(Debug, Clone, Copy)]
+pub enum ViewLayout {
+    Mono,
+    /// Left and right eye, each offset by half the interpupillary distance on X
+    Stereo {
+        ipd: f32,
+    },
+}
+impl ViewLayout {
+    pub fn count(&self) -> u32 {
+        match self {
+            ViewLayout::Mono => 1,
+            ViewLayout::Stereo { .. } => 2,
+        }
+    }
+
+    fn offsets(&self) -> Vec<Vec3> {
+        match self {
+            ViewLayout::Mono => vec![Vec3::ZERO],
+            ViewLayout::Stereo { ipd } => vec![
+                Vec3::new(-ipd / 2.0, 0.0, 0.0),
+                Vec3::new(ipd / 2.0, 0.0, 0.0),
+            ],
+        }
+    }
+
+    /// One [`View`] per array layer of the CME image, in layer order. `view_offset` moves all
+    /// views relative to the camera.
+    pub fn views(&self, projection_matrix: Mat4, view_offset: Vec3) -> Vec<View> {
+        self.offsets()
+            .into_iter()
+            .map(|offset| view(projection_matrix, offset + view_offset))
+            .collect()
+    }
+
+    /// Only the [`View`] of `eye`, for eyes rendering into CME images of their own.
+    pub fn eye_view(&self, eye: usize, projection_matrix: Mat4, view_offset: Vec3) -> Vec<View> {
+        vec![view(projection_matrix, self.offsets()[eye] + view_offset)]
+    }
+}
+
+fn view(projection_matrix: Mat4, offset: Vec3) -> View {
+    View {
+        projection_matrix: projection_matrix.into(),
+        camera_relative_transform: if offset == Vec3::ZERO {
+            Transform::none()
+        } else {
+            Transform::from_translation(offset)
+        },
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Warns when the server stops delivering `RootEvent::Frame` for longer than a timeout, which
+/// otherwise looks like the client hanging.
+pub struct Watchdog {
+    last_frame: Arc<Mutex<Instant>>,
+    stalled: CancellationToken,
+    task: JoinHandle<()>,
+}
+impl Watchdog {
+    pub fn spawn(timeout: Duration) -> Self {
+        let last_frame = Arc::new(Mutex::new(Instant::now()));
+        let stalled = CancellationToken::new();
+        let task = tokio::spawn({
+            let last_frame = last_frame.clone();
+            let stalled = stalled.clone();
+            async move {
+                let mut warned = false;
+                loop {
+                    tokio::time::sleep(timeout / 4).await;
+                    let since_frame = last_frame.lock().unwrap().elapsed();
+                    if since_frame < timeout {
+                        if warned {
+                            info!("the server is delivering frames again");
+                            warned = false;
+                        }
+                    } else if !warned {
+                        warn!(?since_frame, "the server stopped delivering frames");
+                        warned = true;
+                        stalled.cancel();
+                    }
+                }
+            }
+        });
+        Self {
+            last_frame,
+            stalled,
+            task,
+        }
+    }
+
+    /// Call whenever a `RootEvent::Frame` arrives.
+    pub fn frame_delivered(&self) {
+        *self.last_frame.lock().unwrap() = Instant::now();
+    }
+}
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Completes once `watchdog` first noticed a stall, never without one.
+pub async fn stalled(watchdog: Option<&Watchdog>) {
+    match watchdog {
+        Some(watchdog) => watchdog.stalled.cancelled().await,
+        None => future::pending().await,
+    }
+}
@@ -0,0 +1,1801 @@
+use std::{
+    mem,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use glam::Vec3;
+use stardust_xr_cme::swapchain::Swapchain;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use vulkano::{
+    Validated, VulkanError,
+    command_buffer::{
+        AutoCommandBufferBuilder, ClearColorImageInfo, PrimaryAutoCommandBuffer,
+        SemaphoreSubmitInfo,
+    },
+    device::{Device, DeviceOwned, Queue, QueueGuard},
+    format::Format,
+    image::{Image, ImageUsage},
+    instance::Instance,
+    memory::allocator::StandardMemoryAllocator,
+    swapchain::{
+        AcquireNextImageInfo, ColorSpace, CompositeAlpha, CompositeAlphas, PresentInfo,
+        PresentMode, SemaphorePresentInfo, Surface, SwapchainCreateInfo, SwapchainPresentInfo,
+    },
+    sync::{Sharing, semaphore::Semaphore},
+};
+use winit::{
+    application::ApplicationHandler,
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::ActiveEventLoop,
+    keyboard::{Key, KeyCode, PhysicalKey},
+    monitor::MonitorHandle,
+    window::{CursorGrabMode, Fullscreen, Window, WindowAttributes, WindowLevel},
+};
+
+use crate::{
+    bracket::BracketShot,
+    connection::Connection,
+    error::{FrameContext, FrameError, SetupContext, SetupError},
+    fly_camera::FlyCameras,
+    frame::{
+        CME_FORMATS, Flip, FrameSink, HDR_CME_FORMAT, Scaling, create_cme_swapchain,
+        side_by_side_blit, view_size,
+    },
+    gpu::QueueFamilies,
+    grid::Grid,
+    orbit_camera::{DEFAULT_RADIUS, OrbitCamera},
+    overlay::Overlay,
+    projection::Zoom,
+    readback::Readback,
+    replay::ReplayRing,
+    test_pattern::TestPattern,
+    tone_map::{ColorCorrection, CorrectedImage, ToneMapper},
+};
+
+/// Frames a CME swapchain replaced by a resize is kept around for, the server samples the last
+/// submitted image until the next one arrives.
+const RETIRED_FRAMES: u32 = 3;
+/// Most frames a suboptimal window swapchain waits before it's recreated again
+const MAX_SUBOPTIMAL_BACKOFF: u32 = 256;
+/// How often `--title-stats` updates the window title
+const TITLE_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks a window swapchain that still presents but no longer matches the surface. It's
+/// recreated at the start of a frame rather than right away, and each time the recreated one is
+/// suboptimal too the next recreation waits twice as many frames, so a surface that stays
+/// suboptimal doesn't recreate every frame.
+#[derive(Default)]
+struct Suboptimal {
+    reported: bool,
+    /// Frames the recreation after the next one waits
+    backoff: u32,
+    /// Frames left until the next recreation
+    wait: u32,
+}
+impl Suboptimal {
+    fn report(&mut self) {
+        self.reported = true;
+    }
+
+    /// An image was acquired without being suboptimal, so the last recreation converged.
+    fn optimal(&mut self) {
+        if !self.reported {
+            self.backoff = 0;
+        }
+    }
+
+    /// Whether to recreate before this frame, counting down the wait otherwise.
+    fn due(&mut self) -> bool {
+        if !self.reported {
+            return false;
+        }
+        if self.wait > 0 {
+            self.wait -= 1;
+            return false;
+        }
+        true
+    }
+
+    fn recreated(&mut self) {
+        self.reported = false;
+        self.wait = self.backoff;
+        self.backoff = (self.backoff * 2).clamp(1, MAX_SUBOPTIMAL_BACKOFF);
+    }
+}
+
+pub struct Output {
+    window: Arc<Window>,
+    /// Size to restore when leaving fullscreen
+    windowed_size: Option<PhysicalSize<u32>>,
+    swapchain: Arc<vulkano::swapchain::Swapchain>,
+    swap_images: Vec<Arc<Image>>,
+    /// One per grid cell
+    cme_swapchains: Vec<Mutex<Swapchain>>,
+    /// CME swapchains replaced by a resize and the frames left until they are dropped, the
+    /// server may still sample their last images until it got newer ones
+    retired_cme_swapchains: Vec<(Vec<Mutex<Swapchain>>, u32)>,
+    /// The connection's format unless switched with F4
+    cme_format: Format,
+    /// Picked by [`Self::cycle_cme_format`], replaces `cme_format` once the CME swapchains are
+    /// recreated
+    next_cme_format: Option<Format>,
+    /// Only created with `--hdr` or a color correction, used instead of the blit when it
+    /// applies to the CME image
+    tone_mapper: Option<ToneMapper>,
+    pub size: PhysicalSize<u32>,
+    /// Fixed size of each view, otherwise the views follow the window size
+    render_size: Option<PhysicalSize<u32>>,
+    /// Requested multiple of the window size views following the window are rendered at
+    supersample: f32,
+    /// The device's largest 2D image, bounds the supersampled views
+    max_image_dimension: u32,
+    /// Fixed size of the left and right eye's cell with `--left-res` and `--right-res`, each
+    /// cell then has a single view
+    eye_sizes: Option<[PhysicalSize<u32>; 2]>,
+    views: u32,
+    grid: Grid,
+    /// The grid cell rendered next, `None` for a status frame, see [`Self::set_cell`]
+    cell: Option<usize>,
+    scaling: Scaling,
+    flip: Option<Flip>,
+    /// Color the window image is cleared to, premultiplied if the window is transparent
+    background: [f32; 4],
+    /// Set when the render queue's family can't present, the present then waits on the render's
+    /// release semaphore from this queue instead
+    present_queue: Option<Arc<Queue>>,
+    /// How long to wait for a window image before skipping the frame
+    acquire_timeout: Duration,
+    /// Set with `--title-stats`, see [`Self::update_title_stats`]
+    title_stats: bool,
+    /// When the title last showed the stats
+    title_updated: Option<Instant>,
+    pub recreate: bool,
+    suboptimal: Suboptimal,
+    /// Set when the device or the surface is lost, only setting up a new device recovers from that
+    pub lost: bool,
+    pub overlay: Overlay,
+    /// Shown in status frames instead of a CME image
+    test_pattern: TestPattern,
+    memalloc: Arc<StandardMemoryAllocator>,
+    /// Pending copy of the next CME image, written out once the frame completed
+    screenshot: Option<Readback>,
+    /// Like `screenshot` but for a frame of an exposure bracket, read back from `bracket_pass`
+    bracket_shot: Option<(Readback, PathBuf)>,
+    /// Applies a bracket frame's exposure, kept for the next frames of the bracket
+    bracket_pass: Option<CorrectedImage>,
+    /// Set with `--replay-seconds`, dumped on F9
+    replay: Option<ReplayRing>,
+    semaphores: SemaphoreRing,
+    acquired: Option<AcquiredImage>,
+    /// Keeps the previous frame's semaphores alive while it may still be in flight
+    presented: Option<AcquiredImage>,
+}
+struct AcquiredImage {
+    index: u32,
+    acquire_sema: Arc<Semaphore>,
+    release_sema: Arc<Semaphore>,
+}
+
+/// Semaphores for acquiring and presenting window images, reused across frames instead of
+/// allocating two every frame.
+struct SemaphoreRing {
+    acquire: Vec<Arc<Semaphore>>,
+    next_acquire: usize,
+    /// One per window image
+    release: Vec<Arc<Semaphore>>,
+}
+impl SemaphoreRing {
+    fn new(dev: &Arc<Device>, image_count: usize) -> Result<Self, FrameError> {
+        let semaphore = |_| {
+            Semaphore::from_pool(dev.clone())
+                .map(Arc::new)
+                .frame_context("creating the window semaphores")
+        };
+        Ok(Self {
+            acquire: (0..image_count + 1)
+                .map(semaphore)
+                .collect::<Result<_, _>>()?,
+            next_acquire: 0,
+            release: (0..image_count).map(semaphore).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The renderer waits for the previous submission before acquiring, so by then the
+    /// submission waiting on any older acquire semaphore has completed.
+    fn next_acquire(&mut self) -> Arc<Semaphore> {
+        let semaphore = self.acquire[self.next_acquire].clone();
+        self.next_acquire = (self.next_acquire + 1) % self.acquire.len();
+        semaphore
+    }
+
+    /// Only the present of image `index` waits on its release semaphore, and acquiring that image
+    /// again means the present finished.
+    fn release(&self, index: u32) -> Arc<Semaphore> {
+        self.release[index as usize].clone()
+    }
+}
+impl Output {
+    /// Size of each view in `cell`'s CME swapchain, the views share their grid cell side by side.
+    pub fn view_size(&self, cell: usize) -> PhysicalSize<u32> {
+        if let Some(eye_sizes) = self.eye_sizes {
+            return eye_sizes[cell];
+        }
+        self.render_size.unwrap_or_else(|| {
+            let size = view_size(self.grid.cell_size(self.size), self.views);
+            let factor = self.supersample_factor(size);
+            PhysicalSize::new(
+                (size.width as f32 * factor) as u32,
+                (size.height as f32 * factor) as u32,
+            )
+        })
+    }
+
+    /// `--supersample`, lowered so a view of `size` stays within the device's image size limit.
+    /// Both sides are scaled alike, so the aspect ratio and with it the scaling into the window
+    /// stay the same.
+    fn supersample_factor(&self, size: PhysicalSize<u32>) -> f32 {
+        let largest = size.width.max(size.height).max(1);
+        self.supersample
+            .min(self.max_image_dimension as f32 / largest as f32)
+            .max(1.0)
+    }
+
+    /// Selects the grid cell rendered next. All cells are rendered in order for every window
+    /// frame, the window image is acquired with the first one and presented with the last.
+    /// Status frames cover the whole window with a single submission and use `None`.
+    pub fn set_cell(&mut self, cell: Option<usize>) {
+        self.cell = cell;
+    }
+
+    fn starts_frame(&self) -> bool {
+        self.cell.is_none_or(|cell| cell == 0)
+    }
+
+    fn ends_frame(&self) -> bool {
+        self.cell.is_none_or(|cell| cell + 1 == self.grid.count())
+    }
+
+    /// Captures the first cell's next rendered CME image at full render resolution.
+    pub fn request_screenshot(&mut self) {
+        match Readback::new(
+            self.memalloc.clone(),
+            self.view_size(0),
+            self.views,
+            self.cme_format,
+        ) {
+            Ok(readback) => self.screenshot = Some(readback),
+            Err(err) => error!("failed to allocate the screenshot buffer: {err}"),
+        }
+    }
+
+    /// Writes out the frames kept for `--replay-seconds` as a clip.
+    pub fn request_replay(&mut self) {
+        match &mut self.replay {
+            Some(replay) => replay.request_dump(),
+            None => warn!("no frames are kept for a replay, set --replay-seconds"),
+        }
+    }
+
+    /// Reads the next CME image back with `shot`'s exposure applied.
+    pub fn request_bracket_shot(&mut self, shot: &BracketShot) {
+        let size = self.view_size(0);
+        let pass = match self.bracket_pass.take() {
+            Some(mut pass) if pass.fits(size, self.views, self.cme_format) => {
+                pass.set_correction(shot.correction);
+                Ok(pass)
+            }
+            _ => CorrectedImage::new(
+                &self.memalloc,
+                size,
+                self.views,
+                self.cme_format,
+                shot.correction,
+            ),
+        };
+        let readback = Readback::new(self.memalloc.clone(), size, self.views, self.cme_format);
+        match (pass, readback) {
+            (Ok(pass), Ok(readback)) => {
+                self.bracket_pass = Some(pass);
+                self.bracket_shot = Some((readback, shot.path.clone()));
+            }
+            (Err(err), _) => error!("failed to set up the exposure bracket pass: {err}"),
+            (_, Err(err)) => error!("failed to allocate the bracket buffer: {err}"),
+        }
+    }
+
+    /// Marks the window title while paused, since paused frames aren't drawn there's no room for
+    /// it in the overlay.
+    pub fn set_paused(&self, paused: bool) {
+        if paused {
+            self.window.set_title(&format!("{TITLE} - PAUSED"));
+        } else {
+            self.window.set_title(TITLE);
+        }
+    }
+
+    /// Shows the frame rate and the CME image's resolution in the title with `--title-stats`,
+    /// a lighter alternative to the overlay. Updates at most every [`TITLE_STATS_INTERVAL`].
+    pub fn update_title_stats(&mut self, fps: f64, resolution: [u32; 2]) {
+        if !self.title_stats
+            || self
+                .title_updated
+                .is_some_and(|updated| updated.elapsed() < TITLE_STATS_INTERVAL)
+        {
+            return;
+        }
+        self.title_updated = Some(Instant::now());
+        let [width, height] = resolution;
+        self.window
+            .set_title(&format!("{TITLE} - {width}x{height} @ {fps:.1} fps"));
+    }
+
+    /// Locks and hides the cursor for mouse-look, only confining it to the window where locking
+    /// isn't supported.
+    pub fn grab_cursor(&self, grab: bool) {
+        let result = if grab {
+            self.window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(err) = result {
+            warn!(grab, "failed to change the cursor grab: {err}");
+        }
+        self.window.set_cursor_visible(!grab);
+    }
+
+    /// Switches between windowed and borderless fullscreen, the resulting resize recreates the
+    /// swapchains.
+    pub fn toggle_fullscreen(&mut self) {
+        if self.window.fullscreen().is_some() {
+            info!("leaving fullscreen");
+            self.window.set_fullscreen(None);
+            if let Some(size) = self.windowed_size.take() {
+                // applied asynchronously, the resize event follows once it is
+                let _ = self.window.request_inner_size(size);
+            }
+        } else {
+            info!("entering fullscreen");
+            self.windowed_size = Some(self.window.inner_size());
+            self.window
+                .set_fullscreen(Some(Fullscreen::Borderless(None)));
+        }
+    }
+
+    /// Format of the current CME swapchains.
+    pub fn cme_format(&self) -> Format {
+        self.cme_format
+    }
+
+    /// Switches the CME swapchains to the next format out of [`CME_FORMATS`] and
+    /// [`HDR_CME_FORMAT`] the server supports, the formats the blit and the readbacks handle.
+    /// They are recreated before the next frame, like after a resize. The tone mapping pass is
+    /// created if the HDR format is picked without it.
+    pub fn cycle_cme_format(&mut self, connection: &Connection) {
+        let formats = switchable_cme_formats(connection);
+        let current = self.next_cme_format.unwrap_or(self.cme_format);
+        let next = formats
+            .iter()
+            .position(|format| *format == current)
+            .map_or(0, |current| (current + 1) % formats.len());
+        let Some(&format) = formats.get(next) else {
+            return;
+        };
+        if format == HDR_CME_FORMAT && self.tone_mapper.is_none() {
+            match ToneMapper::new(
+                self.memalloc.device(),
+                self.swapchain.image_format(),
+                true,
+                None,
+            ) {
+                Ok(tone_mapper) => self.tone_mapper = Some(tone_mapper),
+                Err(err) => {
+                    warn!("failed to create the tone mapping pass, blitting instead: {err}")
+                }
+            }
+        }
+        info!(?format, "switching the CME format");
+        self.next_cme_format = Some(format);
+        self.recreate = true;
+    }
+
+    /// False while either the window or a view has no area, e.g. when minimized. Nothing is
+    /// rendered and no swapchain is recreated until it has one again.
+    pub fn has_area(&self) -> bool {
+        let view_size = self.view_size(0);
+        self.size.width != 0
+            && self.size.height != 0
+            && view_size.width != 0
+            && view_size.height != 0
+    }
+
+    /// Rebuilds the window swapchain at `self.size`, and the CME swapchains too unless they have
+    /// a fixed render size. The cameras stay, they get the new swapchains' images with the next
+    /// submit. No frame may be in flight, see [`Renderer::wait_in_flight`].
+    ///
+    /// [`Renderer::wait_in_flight`]: crate::frame::Renderer::wait_in_flight
+    pub fn recreate_swapchains(
+        &mut self,
+        connection: &Connection,
+        dev: &Arc<Device>,
+    ) -> Result<(), FrameError> {
+        info!(size = ?self.size, "recreating swapchains");
+        self.recreate_window_swapchain()?;
+        // without a fixed render size they follow the window, they are also missing if the
+        // window had no area when they were last created
+        let fixed_size = self.render_size.is_some() || self.eye_sizes.is_some();
+        if !fixed_size || self.cme_swapchains.is_empty() || self.next_cme_format.is_some() {
+            let previous_format = self.cme_format;
+            if let Some(format) = self.next_cme_format.take() {
+                self.cme_format = format;
+            }
+            // a format switched to with F4 is dropped again if it fails
+            let new = self.create_cme_swapchains(connection, dev).map_err(|err| {
+                self.cme_format = previous_format;
+                FrameError::Swapchain(Box::new(err))
+            })?;
+            let old = mem::replace(&mut self.cme_swapchains, new);
+            self.retired_cme_swapchains.push((old, RETIRED_FRAMES));
+        }
+        self.recreate = false;
+        self.suboptimal = Suboptimal::default();
+        Ok(())
+    }
+
+    /// Whether the window swapchain was suboptimal long enough to recreate it before this frame,
+    /// see [`Self::recreate_suboptimal`].
+    pub fn suboptimal_due(&mut self) -> bool {
+        self.suboptimal.due()
+    }
+
+    /// Rebuilds the suboptimal window swapchain with the same settings, the CME swapchains don't
+    /// depend on the surface. No frame may be in flight.
+    pub fn recreate_suboptimal(&mut self) -> Result<(), FrameError> {
+        info!("recreating the suboptimal window swapchain");
+        self.recreate_window_swapchain()?;
+        self.suboptimal.recreated();
+        Ok(())
+    }
+
+    /// Call once per rendered frame, drops retired CME swapchains the server is done with.
+    pub fn frame_rendered(&mut self) {
+        self.retired_cme_swapchains.retain_mut(|(_, frames_left)| {
+            *frames_left -= 1;
+            *frames_left > 0
+        });
+    }
+
+    /// Rebuilds only the window swapchain at `self.size`, the CME swapchains are left alone since
+    /// they can't be recreated without a connection.
+    pub fn recreate_window_swapchain(&mut self) -> Result<(), FrameError> {
+        let (swapchain, swap_images) = self
+            .swapchain
+            .recreate(SwapchainCreateInfo {
+                image_extent: self.size.into(),
+                ..self.swapchain.create_info()
+            })
+            .frame_context("recreating the window swapchain")?;
+        // the old swapchain's presents may still wait on the old semaphores, `presented` keeps
+        // the last ones alive
+        self.semaphores = SemaphoreRing::new(swapchain.device(), swap_images.len())?;
+        self.swapchain = swapchain;
+        self.swap_images = swap_images;
+        Ok(())
+    }
+
+    /// Replaces the CME swapchains with ones shared with the new connection's server.
+    pub fn reconnect(
+        &mut self,
+        connection: &Connection,
+        dev: &Arc<Device>,
+    ) -> Result<(), SetupError> {
+        self.cme_format = connection.cme_format;
+        self.next_cme_format = None;
+        // a zero sized Dmatex can't be created, they are created with the next recreate once the
+        // window has an area again
+        if self.has_area() {
+            self.cme_swapchains = self.create_cme_swapchains(connection, dev)?;
+        } else {
+            self.cme_swapchains.clear();
+            self.recreate = true;
+        }
+        // shared with the old server, which is gone
+        self.retired_cme_swapchains.clear();
+        Ok(())
+    }
+
+    fn create_cme_swapchains(
+        &self,
+        connection: &Connection,
+        dev: &Arc<Device>,
+    ) -> Result<Vec<Mutex<Swapchain>>, SetupError> {
+        let window_view_size = view_size(self.grid.cell_size(self.size), self.views);
+        let factor = self.supersample_factor(window_view_size);
+        if self.render_size.is_none() && factor < self.supersample {
+            warn!(
+                requested = self.supersample,
+                factor,
+                max_image_dimension = self.max_image_dimension,
+                "lowered the supersampling to fit the gpu's image size limit"
+            );
+        }
+        (0..self.grid.count())
+            .map(|cell| {
+                create_cme_swapchain(
+                    connection,
+                    dev,
+                    self.cme_format,
+                    self.view_size(cell),
+                    self.views,
+                )
+                .map(Mutex::new)
+            })
+            .collect()
+    }
+}
+impl FrameSink for Output {
+    fn cme_swapchain(&mut self) -> &mut Swapchain {
+        self.cme_swapchains[self.cell.unwrap_or(0)]
+            .get_mut()
+            .unwrap()
+    }
+
+    fn acquire(&mut self) -> bool {
+        if !self.starts_frame() {
+            // the first cell failing to acquire skips the whole frame
+            return self.acquired.is_some();
+        }
+        let acquire_sema = self.semaphores.next_acquire();
+        let acquired = match unsafe {
+            self.swapchain.acquire_next_image(&AcquireNextImageInfo {
+                timeout: Some(self.acquire_timeout),
+                semaphore: Some(acquire_sema.clone()),
+                ..Default::default()
+            })
+        } {
+            Ok(acquired) => acquired,
+            Err(Validated::Error(VulkanError::OutOfDate)) => {
+                self.recreate = true;
+                return false;
+            }
+            Err(Validated::Error(VulkanError::Timeout | VulkanError::NotReady)) => {
+                warn!(timeout = ?self.acquire_timeout, "timed out acquiring a window image");
+                return false;
+            }
+            Err(Validated::Error(err @ (VulkanError::DeviceLost | VulkanError::SurfaceLost))) => {
+                error!("failed to acquire a window image: {err}");
+                self.lost = true;
+                return false;
+            }
+            Err(err) => {
+                warn!("failed to acquire a window image, skipping the frame: {err}");
+                self.recreate = true;
+                return false;
+            }
+        };
+        if acquired.is_suboptimal {
+            self.suboptimal.report();
+        } else {
+            self.suboptimal.optimal();
+        }
+        self.acquired = Some(AcquiredImage {
+            index: acquired.image_index,
+            acquire_sema,
+            release_sema: self.semaphores.release(acquired.image_index),
+        });
+        true
+    }
+
+    fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        let cell = self.cell.unwrap_or(0);
+        if cell == 0
+            && let Some(screenshot) = &self.screenshot
+        {
+            screenshot.record(builder, image.clone())?;
+        }
+        if cell == 0
+            && let Some((readback, _)) = &self.bracket_shot
+            && let Some(pass) = &self.bracket_pass
+        {
+            // the readback is in the same submit as the pass, so it always sees this exposure
+            readback.record(builder, pass.record(builder, image.clone())?)?;
+        }
+        if cell == 0
+            && let Some(replay) = &mut self.replay
+        {
+            replay.record(
+                builder,
+                image.clone(),
+                self.view_size(0),
+                self.views,
+                self.cme_format,
+            )?;
+        }
+        let acquired = self.acquired.as_ref().unwrap();
+        let way_image = self.swap_images[acquired.index as usize].clone();
+        let [width, height, _] = way_image.extent();
+        let cell_rect = self.grid.cell_rect(PhysicalSize::new(width, height), cell);
+        if self.starts_frame() && self.scaling == Scaling::Fit {
+            builder
+                .clear_color_image(ClearColorImageInfo {
+                    clear_value: self.background.into(),
+                    ..ClearColorImageInfo::image(way_image.clone())
+                })
+                .frame_context("clearing the window image")?;
+        }
+        if let Some(tone_mapper) = &self.tone_mapper
+            && tone_mapper.applies_to(&image)
+        {
+            tone_mapper.record(
+                builder,
+                image,
+                way_image.clone(),
+                cell_rect,
+                self.scaling,
+                self.flip,
+            )?;
+        } else {
+            builder
+                .blit_image(side_by_side_blit(
+                    image,
+                    way_image.clone(),
+                    cell_rect,
+                    self.scaling,
+                    self.flip,
+                ))
+                .frame_context("blitting the CME image")?;
+        }
+        if self.ends_frame() {
+            self.overlay.record(builder, way_image)?;
+        }
+        Ok(())
+    }
+
+    fn record_status(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), FrameError> {
+        let acquired = self.acquired.as_ref().unwrap();
+        let way_image = self.swap_images[acquired.index as usize].clone();
+        self.test_pattern.record(builder, way_image.clone())?;
+        self.overlay.draw(builder, way_image)
+    }
+
+    fn wait_semaphores(&self) -> Vec<SemaphoreSubmitInfo> {
+        if !self.starts_frame() {
+            return Vec::new();
+        }
+        let acquired = self.acquired.as_ref().unwrap();
+        vec![SemaphoreSubmitInfo::new(acquired.acquire_sema.clone())]
+    }
+
+    fn signal_semaphores(&self) -> Vec<SemaphoreSubmitInfo> {
+        if !self.ends_frame() {
+            return Vec::new();
+        }
+        let acquired = self.acquired.as_ref().unwrap();
+        vec![SemaphoreSubmitInfo::new(acquired.release_sema.clone())]
+    }
+
+    fn after_submit(&mut self, queue: &mut QueueGuard<'_>) {
+        if !self.ends_frame() {
+            return;
+        }
+        let acquired = self.acquired.take().unwrap();
+        let present_info = PresentInfo {
+            wait_semaphores: vec![SemaphorePresentInfo::new(acquired.release_sema.clone())],
+            swapchain_infos: vec![SwapchainPresentInfo::swapchain_image_index(
+                self.swapchain.clone(),
+                acquired.index,
+            )],
+            ..Default::default()
+        };
+        let result = match &self.present_queue {
+            Some(present_queue) => present_queue.with(|mut present_queue| unsafe {
+                present_queue
+                    .present(&present_info)
+                    .map(|mut results| results.next())
+            }),
+            None => unsafe { queue.present(&present_info) }.map(|mut results| results.next()),
+        };
+        match result {
+            Ok(Some(Ok(true))) => self.suboptimal.report(),
+            Ok(Some(Err(VulkanError::OutOfDate))) => self.recreate = true,
+            Ok(Some(Ok(false)) | None) => {}
+            Err(Validated::Error(VulkanError::OutOfDate)) => self.recreate = true,
+            Ok(Some(Err(err @ (VulkanError::DeviceLost | VulkanError::SurfaceLost))))
+            | Err(Validated::Error(err @ (VulkanError::DeviceLost | VulkanError::SurfaceLost))) => {
+                error!("failed to present the window image: {err}");
+                self.lost = true;
+            }
+            Ok(Some(Err(err))) => {
+                warn!("failed to present the window image: {err}");
+                self.recreate = true;
+            }
+            Err(err) => {
+                warn!("failed to present the window image: {err}");
+                self.recreate = true;
+            }
+        }
+        self.presented = Some(acquired);
+    }
+
+    fn discard(&mut self) {
+        // the acquired image never gets presented, a new swapchain doesn't wait on it
+        if self.acquired.take().is_some() {
+            self.recreate = true;
+        }
+    }
+
+    fn wait_for_completion(&self) -> bool {
+        self.screenshot.is_some() || self.bracket_shot.is_some()
+    }
+
+    fn complete(&mut self) {
+        if let Some((readback, path)) = self.bracket_shot.take() {
+            match readback.write_png(&path) {
+                Ok(()) => info!(path = %path.display(), "saved bracket frame"),
+                Err(err) => error!(path = %path.display(), "failed to save bracket frame: {err}"),
+            }
+        }
+        let Some(screenshot) = self.screenshot.take() else {
+            return;
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = PathBuf::from(format!(
+            "screenshot-{}.{:03}.png",
+            timestamp.as_secs(),
+            timestamp.subsec_millis()
+        ));
+        match screenshot.write_png(&path) {
+            Ok(()) => info!(path = %path.display(), "saved screenshot"),
+            Err(err) => error!(path = %path.display(), "failed to save screenshot: {err}"),
+        }
+    }
+}
+
+/// Surface formats in order of preference, the SRGB ones match the CME swapchain's encoding.
+const SRGB_SURFACE_FORMATS: [Format; 2] = [Format::B8G8R8A8_SRGB, Format::R8G8B8A8_SRGB];
+const UNORM_SURFACE_FORMATS: [Format; 2] = [Format::B8G8R8A8_UNORM, Format::R8G8B8A8_UNORM];
+/// Preferred for an HDR `--color-space`, otherwise the first format paired with it is used
+const HDR_SURFACE_FORMATS: [Format; 3] = [
+    Format::R16G16B16A16_SFLOAT,
+    Format::A2B10G10R10_UNORM_PACK32,
+    Format::A2R10G10B10_UNORM_PACK32,
+];
+
+/// Picks the window's surface format and color space. A `requested` color space the surface
+/// doesn't support falls back to SRGB with a warning.
+pub fn choose_surface_format(
+    formats: &[(Format, ColorSpace)],
+    requested: Option<ColorSpace>,
+) -> Result<(Format, ColorSpace), SetupError> {
+    if let Some(requested) =
+        requested.filter(|color_space| *color_space != ColorSpace::SrgbNonLinear)
+    {
+        let paired: Vec<_> = formats
+            .iter()
+            .filter(|(_, color_space)| *color_space == requested)
+            .map(|(format, _)| *format)
+            .collect();
+        match HDR_SURFACE_FORMATS
+            .into_iter()
+            .find(|format| paired.contains(format))
+            .or(paired.first().copied())
+        {
+            Some(format) => return Ok((format, requested)),
+            None => warn!(
+                ?requested,
+                supported = ?formats,
+                "the surface doesn't support the requested color space, falling back to SRGB"
+            ),
+        }
+    }
+    let supported = |wanted: &Format| {
+        formats.iter().any(|(format, color_space)| {
+            format == wanted && *color_space == ColorSpace::SrgbNonLinear
+        })
+    };
+    if let Some(format) = SRGB_SURFACE_FORMATS.into_iter().find(supported) {
+        return Ok((format, ColorSpace::SrgbNonLinear));
+    }
+    if let Some(format) = UNORM_SURFACE_FORMATS.into_iter().find(supported) {
+        warn!(
+            ?format,
+            "no SRGB surface format available, the blit will write linear values so the \
+             preview will look darker than the rendered image"
+        );
+        return Ok((format, ColorSpace::SrgbNonLinear));
+    }
+    Err(SetupError::NoUsableSurfaceFormat(
+        formats.iter().map(|(format, _)| *format).collect(),
+    ))
+}
+
+/// `--image-count` clamped to what the surface supports, at least double buffered without it.
+fn choose_image_count(requested: Option<u32>, min: u32, max: Option<u32>) -> u32 {
+    let max = max.unwrap_or(u32::MAX);
+    let Some(requested) = requested else {
+        return min.max(2).min(max);
+    };
+    let count = requested.clamp(min, max);
+    if count != requested {
+        warn!(
+            requested,
+            min, max, "image count unsupported by the surface, clamping it"
+        );
+    }
+    count
+}
+
+/// Picks premultiplied alpha for `--transparent`, falling back to an opaque window if the
+/// compositor doesn't support it.
+fn choose_composite_alpha(supported: CompositeAlphas, transparent: bool) -> CompositeAlpha {
+    if transparent {
+        if supported.contains_enum(CompositeAlpha::PreMultiplied) {
+            return CompositeAlpha::PreMultiplied;
+        }
+        warn!(
+            ?supported,
+            "premultiplied alpha is unsupported, the window will be opaque"
+        );
+    }
+    [CompositeAlpha::Opaque, CompositeAlpha::Inherit]
+        .into_iter()
+        .find(|mode| supported.contains_enum(*mode))
+        .or_else(|| supported.into_iter().next())
+        .unwrap_or(CompositeAlpha::Opaque)
+}
+
+/// The CME formats F4 cycles through, those out of [`CME_FORMATS`] and [`HDR_CME_FORMAT`] the
+/// server supports.
+fn switchable_cme_formats(connection: &Connection) -> Vec<Format> {
+    CME_FORMATS
+        .into_iter()
+        .chain([HDR_CME_FORMAT])
+        .filter(|format| connection.formats.contains_key(format))
+        .collect()
+}
+
+pub struct WinitApp {
+    pub output: Arc<Mutex<Option<Output>>>,
+    pub dev: Arc<Device>,
+    pub queue_families: QueueFamilies,
+    /// The render queue unless the `queue_families` are split
+    pub present_queue: Arc<Queue>,
+    pub memalloc: Arc<StandardMemoryAllocator>,
+    /// Set on F12, the stardust loop takes it and captures the next frame
+    pub screenshot: Arc<AtomicBool>,
+    /// Set on F10, the stardust loop takes it and captures an exposure bracket
+    pub bracket: Arc<AtomicBool>,
+    /// How many frames `--replay-seconds` keeps for F9
+    pub replay_frames: Option<usize>,
+    /// Toggled with space, the stardust loop skips frames while set
+    pub paused: Arc<AtomicBool>,
+    /// Whether losing focus sets [`Self::blurred`]
+    pub pause_on_blur: bool,
+    /// Set while unfocused with `--pause-on-blur`, the stardust loop then skips or throttles
+    /// frames
+    pub blurred: Arc<AtomicBool>,
+    /// Toggled with F2
+    pub show_frustum: Arc<AtomicBool>,
+    /// Set on F3, the stardust loop takes it and logs the camera matrices
+    pub dump_matrices: Arc<AtomicBool>,
+    /// Toggled with F5
+    pub unlit: Arc<AtomicBool>,
+    /// Whether the panel is shown in the scene, F5 only changes its lighting
+    pub preview: bool,
+    /// Set with a looping `--path`, which moves the primary camera every frame so orbiting
+    /// has no effect
+    pub looping_path: bool,
+    pub instance: Arc<Instance>,
+    /// Replaced by the stardust loop when it reconnects
+    pub connection: Arc<Mutex<Connection>>,
+    pub fly_cameras: Arc<Mutex<FlyCameras>>,
+    /// Set while in orbit mode, toggled with O. Its pose is written into the primary camera.
+    pub orbit_camera: Option<OrbitCamera>,
+    /// Scrolling outside of orbit mode zooms the FOV
+    pub zoom: Arc<Mutex<Zoom>>,
+    pub views: u32,
+    pub grid: Grid,
+    pub scaling: Scaling,
+    pub flip: Option<Flip>,
+    pub transparent: bool,
+    /// Straight alpha `--clear-color`, black otherwise
+    pub clear_color: Option<[f32; 4]>,
+    /// Grab and hide the cursor during mouse-look
+    pub grab_cursor: bool,
+    /// Set with `--look-at`, L aims the primary camera at it
+    pub look_at: Option<Vec3>,
+    /// Index into the available monitors the window is placed on
+    pub monitor: Option<usize>,
+    pub fullscreen: bool,
+    /// Corner of the borderless always-on-top window with `--overlay-window`
+    pub overlay_window: Option<Corner>,
+    /// Whether HDR was requested, the connection may still have fallen back to 8-bit
+    pub hdr: bool,
+    pub color_correction: Option<ColorCorrection>,
+    pub render_size: Option<PhysicalSize<u32>>,
+    /// Multiple of the window size the views are rendered at with `--supersample`
+    pub supersample: Option<f32>,
+    pub eye_sizes: Option<[PhysicalSize<u32>; 2]>,
+    pub present_modes: Vec<PresentMode>,
+    /// Minimum window swapchain images with `--image-count`
+    pub image_count: Option<u32>,
+    /// Requested with `--color-space`, SRGB otherwise
+    pub color_space: Option<ColorSpace>,
+    pub acquire_timeout: Duration,
+    /// Show the frame rate and resolution in the window title with `--title-stats`
+    pub title_stats: bool,
+    pub shutdown: CancellationToken,
+    /// Set when window setup fails, returned from `main` once the event loop exits
+    pub error: Option<SetupError>,
+}
+pub const TITLE: &str = "Stardust Camera";
+const OVERLAY_WINDOW_SIZE: LogicalSize<f64> = LogicalSize::new(480.0, 270.0);
+/// Distance of the overlay window from the monitor's edges, in logical pixels
+const OVERLAY_WINDOW_MARGIN: f64 = 16.0;
+
+/// Where `--overlay-window` places the window on the monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+/// Pixels of touchpad scrolling that count as one scrolled line
+const PIXELS_PER_LINE: f64 = 50.0;
+
+impl WinitApp {
+    fn toggle_orbit(&mut self) {
+        let mut fly_cameras = self.fly_cameras.lock().unwrap();
+        let fly_camera = fly_cameras.primary_mut();
+        fly_camera.reset_input();
+        self.orbit_camera = match self.orbit_camera.take() {
+            Some(_) => {
+                info!("switched to fly camera");
+                None
+            }
+            None => {
+                info!("switched to orbit camera");
+                Some(OrbitCamera::from_fly_camera(fly_camera, DEFAULT_RADIUS))
+            }
+        };
+    }
+
+    /// Moves input to the next grid cell's camera, orbiting in front of it if in orbit mode.
+    fn cycle_primary(&mut self) {
+        let mut fly_cameras = self.fly_cameras.lock().unwrap();
+        fly_cameras.cycle_primary();
+        info!(camera = fly_cameras.primary, "switched primary camera");
+        if self.orbit_camera.is_some() {
+            self.orbit_camera = Some(OrbitCamera::from_fly_camera(
+                fly_cameras.primary(),
+                DEFAULT_RADIUS,
+            ));
+        }
+    }
+
+    /// Snaps the primary camera back to its startup pose and zooms back to the startup FOV.
+    fn reset_camera(&mut self) {
+        let mut fly_cameras = self.fly_cameras.lock().unwrap();
+        fly_cameras.reset_primary();
+        if self.orbit_camera.is_some() {
+            self.orbit_camera = Some(OrbitCamera::from_fly_camera(
+                fly_cameras.primary(),
+                DEFAULT_RADIUS,
+            ));
+        }
+        self.zoom.lock().unwrap().reset();
+        info!("reset the camera");
+    }
+
+    /// The keybindings for `?`, leaving out the ones that do nothing with the current arguments.
+    fn help(&self) -> Vec<String> {
+        let mut help = vec![
+            "WASD QE: MOVE, SHIFT: FAST".to_owned(),
+            "RIGHT MOUSE: LOOK, SCROLL: ZOOM FOV".to_owned(),
+        ];
+        if !self.looping_path {
+            help.push("O: ORBIT, LEFT/RIGHT MOUSE: ROTATE/PAN".to_owned());
+        }
+        help.push("R: RESET CAMERA".to_owned());
+        if self.look_at.is_some() {
+            help.push("L: LOOK AT TARGET".to_owned());
+        }
+        if self.grid.count() > 1 {
+            help.push("TAB: NEXT CAMERA".to_owned());
+        }
+        if self.grab_cursor {
+            help.push("ESC: RELEASE CURSOR".to_owned());
+        }
+        help.extend([
+            "SPACE: PAUSE".to_owned(),
+            "F1: STATS".to_owned(),
+            "F2: FRUSTUM, F3: LOG MATRICES".to_owned(),
+        ]);
+        if switchable_cme_formats(&self.connection.lock().unwrap()).len() > 1 {
+            help.push("F4: NEXT CME FORMAT".to_owned());
+        }
+        if self.preview {
+            help.push("F5: PREVIEW LIGHTING".to_owned());
+        }
+        if self.replay_frames.is_some() {
+            help.push("F9: SAVE REPLAY".to_owned());
+        }
+        help.extend([
+            "F10: EXPOSURE BRACKET".to_owned(),
+            "F11: FULLSCREEN, F12: SCREENSHOT".to_owned(),
+            "?: HIDE HELP".to_owned(),
+        ]);
+        help
+    }
+
+    /// Starts or stops mouse-look on the primary camera, with `--grab-cursor` the cursor is
+    /// grabbed and hidden meanwhile.
+    fn set_looking(&mut self, looking: bool) {
+        self.fly_cameras
+            .lock()
+            .unwrap()
+            .primary_mut()
+            .set_looking(looking);
+        if self.grab_cursor
+            && let Some(output) = self.output.lock().unwrap().as_ref()
+        {
+            output.grab_cursor(looking);
+        }
+    }
+
+    /// Points the primary camera at `--look-at` again, leaving orbit mode since that always looks
+    /// at its pivot.
+    fn aim_at_target(&mut self) {
+        let Some(target) = self.look_at else {
+            return;
+        };
+        if self.orbit_camera.is_some() {
+            self.toggle_orbit();
+        }
+        let mut fly_cameras = self.fly_cameras.lock().unwrap();
+        if fly_cameras.primary_mut().look_at(target) {
+            info!(?target, "aimed the camera at the target");
+        } else {
+            warn!(
+                ?target,
+                "the camera is at the target, there is nothing to aim at"
+            );
+        }
+    }
+
+    /// Runs `f` on the orbit camera if in orbit mode and moves the camera to its new pose.
+    fn update_orbit(&mut self, f: impl FnOnce(&mut OrbitCamera)) -> bool {
+        let Some(orbit_camera) = &mut self.orbit_camera else {
+            return false;
+        };
+        f(orbit_camera);
+        orbit_camera.apply(self.fly_cameras.lock().unwrap().primary_mut());
+        true
+    }
+
+    /// The monitor picked with `--monitor`, otherwise the primary one.
+    fn monitor(&self, event_loop: &ActiveEventLoop) -> Result<Option<MonitorHandle>, SetupError> {
+        let Some(index) = self.monitor else {
+            return Ok(event_loop
+                .primary_monitor()
+                .or_else(|| event_loop.available_monitors().next()));
+        };
+        match event_loop.available_monitors().nth(index) {
+            Some(monitor) => {
+                info!(index, name = monitor.name(), "selected monitor");
+                Ok(Some(monitor))
+            }
+            None => Err(SetupError::NoSuchMonitor {
+                available: event_loop
+                    .available_monitors()
+                    .enumerate()
+                    .map(|(i, monitor)| {
+                        format!(
+                            "{i}: {}",
+                            monitor.name().unwrap_or_else(|| "unnamed".into())
+                        )
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Fullscreen on the monitor with `--fullscreen`, a small borderless always-on-top window in
+    /// a corner of it with `--overlay-window`, otherwise a normal one placed on it if `--monitor`
+    /// is given.
+    fn window_attributes(
+        &self,
+        event_loop: &ActiveEventLoop,
+    ) -> Result<WindowAttributes, SetupError> {
+        let attributes = Window::default_attributes()
+            .with_title(TITLE)
+            .with_transparent(self.transparent);
+        let monitor = self.monitor(event_loop)?;
+        if self.fullscreen {
+            // borderless, Wayland has no exclusive fullscreen
+            return Ok(attributes.with_fullscreen(Some(Fullscreen::Borderless(monitor))));
+        }
+        let Some(corner) = self.overlay_window else {
+            return Ok(match monitor {
+                Some(monitor) if self.monitor.is_some() => {
+                    attributes.with_position(monitor.position())
+                }
+                _ => attributes,
+            });
+        };
+        let attributes = attributes
+            .with_decorations(false)
+            .with_window_level(WindowLevel::AlwaysOnTop)
+            .with_inner_size(OVERLAY_WINDOW_SIZE);
+        let Some(monitor) = monitor else {
+            warn!("no monitor to place the overlay window on");
+            return Ok(attributes);
+        };
+        let scale = monitor.scale_factor();
+        let size = OVERLAY_WINDOW_SIZE.to_physical::<i32>(scale);
+        let margin = (OVERLAY_WINDOW_MARGIN * scale) as i32;
+        let origin = monitor.position();
+        let monitor_size = monitor.size().cast::<i32>();
+        let (left, top) = (origin.x + margin, origin.y + margin);
+        let right = origin.x + monitor_size.width - size.width - margin;
+        let bottom = origin.y + monitor_size.height - size.height - margin;
+        let position = match corner {
+            Corner::TopLeft => PhysicalPosition::new(left, top),
+            Corner::TopRight => PhysicalPosition::new(right, top),
+            Corner::BottomLeft => PhysicalPosition::new(left, bottom),
+            Corner::BottomRight => PhysicalPosition::new(right, bottom),
+        };
+        Ok(attributes.with_position(position))
+    }
+
+    fn create_output(&self, event_loop: &ActiveEventLoop) -> Result<Output, SetupError> {
+        info!("creating new window");
+        let window = Arc::new(
+            event_loop
+                .create_window(self.window_attributes(event_loop)?)
+                .vk_context("creating the window")?,
+        );
+        let surface = Surface::from_window(self.instance.clone(), window.clone())
+            .vk_context("creating the window surface")?;
+        // a fullscreen window may only get the monitor's size with its first resize, the
+        // swapchain starts out at the monitor's video mode instead
+        let window_size = match window.fullscreen() {
+            Some(Fullscreen::Borderless(Some(monitor))) => monitor
+                .current_video_mode()
+                .map_or_else(|| monitor.size(), |mode| mode.size()),
+            _ => window.inner_size(),
+        };
+        info!(?window_size);
+
+        let (image_format, image_color_space) = choose_surface_format(
+            &self
+                .dev
+                .physical_device()
+                .surface_formats(&surface, Default::default())
+                .vk_context("querying surface formats")?,
+            self.color_space,
+        )?;
+        info!(?image_format, ?image_color_space);
+        let supported_present_modes: Vec<_> = self
+            .dev
+            .physical_device()
+            .surface_present_modes(&surface, Default::default())
+            .vk_context("querying surface present modes")?
+            .into_iter()
+            .collect();
+        let present_mode = self
+            .present_modes
+            .iter()
+            .copied()
+            .find(|mode| supported_present_modes.contains(mode))
+            .unwrap_or_else(|| {
+                warn!(
+                    requested = ?self.present_modes,
+                    supported = ?supported_present_modes,
+                    "present mode unsupported, falling back to fifo"
+                );
+                PresentMode::Fifo
+            });
+        info!(?present_mode);
+        let surface_capabilities = self
+            .dev
+            .physical_device()
+            .surface_capabilities(&surface, Default::default())
+            .vk_context("querying surface capabilities")?;
+        let composite_alpha = choose_composite_alpha(
+            surface_capabilities.supported_composite_alpha,
+            self.transparent,
+        );
+        info!(?composite_alpha);
+        let min_image_count = choose_image_count(
+            self.image_count,
+            surface_capabilities.min_image_count,
+            surface_capabilities.max_image_count,
+        );
+        let (swapchain, images) = vulkano::swapchain::Swapchain::new(
+            self.dev.clone(),
+            surface,
+            SwapchainCreateInfo {
+                min_image_count,
+                image_format: image_format,
+                image_color_space,
+                // a window created minimized has no size yet, the real swapchain is created
+                // once it has one
+                image_extent: [window_size.width.max(1), window_size.height.max(1)],
+                image_usage: ImageUsage::TRANSFER_DST | ImageUsage::COLOR_ATTACHMENT,
+                composite_alpha,
+                present_mode,
+                // shared instead of transferring ownership between the render and present queue
+                // every frame, the semaphores already order the two
+                image_sharing: if self.queue_families.is_split() {
+                    Sharing::Concurrent(
+                        vec![self.queue_families.render, self.queue_families.present].into(),
+                    )
+                } else {
+                    Sharing::Exclusive
+                },
+
+                ..Default::default()
+            },
+        )
+        .vk_context("creating the window swapchain")?;
+        // the driver may create more than requested
+        info!(
+            requested = min_image_count,
+            images = images.len(),
+            "window swapchain images"
+        );
+        let connection = self.connection.lock().unwrap().clone();
+        let tone_mapper = (self.hdr || self.color_correction.is_some())
+            .then(|| ToneMapper::new(&self.dev, image_format, true, self.color_correction))
+            .transpose()?;
+        let semaphores = SemaphoreRing::new(&self.dev, images.len())?;
+        let mut output = Output {
+            window,
+            windowed_size: None,
+            swapchain,
+            swap_images: images,
+            cme_swapchains: Vec::new(),
+            retired_cme_swapchains: Vec::new(),
+            cme_format: connection.cme_format,
+            next_cme_format: None,
+            tone_mapper,
+            size: window_size,
+            render_size: self.render_size,
+            supersample: self.supersample.unwrap_or(1.0),
+            max_image_dimension: self
+                .dev
+                .physical_device()
+                .properties()
+                .max_image_dimension2_d,
+            eye_sizes: self.eye_sizes,
+            views: self.views,
+            grid: self.grid,
+            cell: None,
+            scaling: self.scaling,
+            flip: self.flip,
+            background: match (composite_alpha, self.clear_color) {
+                (CompositeAlpha::PreMultiplied, Some([r, g, b, a])) => [r * a, g * a, b * a, a],
+                (CompositeAlpha::PreMultiplied, None) => [0.0; 4],
+                (_, Some([r, g, b, _])) => [r, g, b, 1.0],
+                (_, None) => [0.0, 0.0, 0.0, 1.0],
+            },
+            present_queue: self
+                .queue_families
+                .is_split()
+                .then(|| self.present_queue.clone()),
+            acquire_timeout: self.acquire_timeout,
+            title_stats: self.title_stats,
+            title_updated: None,
+            recreate: false,
+            suboptimal: Suboptimal::default(),
+            lost: false,
+            overlay: Overlay::new(self.memalloc.clone()),
+            test_pattern: TestPattern::new(&self.memalloc)?,
+            memalloc: self.memalloc.clone(),
+            screenshot: None,
+            bracket_shot: None,
+            bracket_pass: None,
+            replay: self
+                .replay_frames
+                .map(|frames| ReplayRing::new(self.memalloc.clone(), frames)),
+            semaphores,
+            acquired: None,
+            presented: None,
+        };
+        if output.has_area() {
+            output.cme_swapchains = output.create_cme_swapchains(&connection, &self.dev)?;
+        } else {
+            output.recreate = true;
+        }
+        output
+            .overlay
+            .set_scale_factor(output.window.scale_factor());
+        output.set_paused(self.paused.load(Ordering::Relaxed));
+        Ok(output)
+    }
+}
+impl ApplicationHandler for WinitApp {
+    /// Can fire again after a suspend, the window and its swapchains outlive that so they are
+    /// reused instead of leaking a second set.
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.output.lock().unwrap().is_some() {
+            info!("reusing the existing window");
+            return;
+        }
+        match self.create_output(event_loop) {
+            Ok(output) => {
+                self.output.lock().unwrap().replace(output);
+            }
+            Err(err) => {
+                error!("{err}");
+                self.error = Some(err);
+                self.shutdown.cancel();
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        window_id: winit::window::WindowId,
+        event: WindowEvent,
+    ) {
+        let ours = self
+            .output
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|output| output.window.id() == window_id);
+        if !ours {
+            return;
+        }
+        match event {
+            WindowEvent::Resized(physical_size) => {
+                if let Some(output) = self.output.lock().unwrap().as_mut() {
+                    output.size = physical_size;
+                    output.recreate = true;
+                }
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                if let Some(output) = self.output.lock().unwrap().as_mut() {
+                    output.overlay.set_scale_factor(scale_factor);
+                    let size = output.window.inner_size();
+                    if size != output.size {
+                        output.size = size;
+                        output.recreate = true;
+                    }
+                }
+            }
+            WindowEvent::CloseRequested => {
+                self.shutdown.cancel();
+                event_loop.exit();
+            }
+            WindowEvent::Destroyed => {
+                // the swapchains can't outlive the window's surface
+                self.output.lock().unwrap().take();
+                self.shutdown.cancel();
+                event_loop.exit();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F1),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(output) = self.output.lock().unwrap().as_mut() {
+                    output.overlay.enabled = !output.overlay.enabled;
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key: Key::Character(c),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } if c.as_str() == "?" => {
+                let help = self.help();
+                if let Some(output) = self.output.lock().unwrap().as_mut() {
+                    let shown = output.overlay.toggle_help(help);
+                    info!(shown, "toggled the keybinding help");
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F2),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let show = !self.show_frustum.fetch_xor(true, Ordering::Relaxed);
+                info!(show, "toggled the frustum outline");
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F3),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.dump_matrices.store(true, Ordering::Relaxed);
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F5),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let unlit = !self.unlit.fetch_xor(true, Ordering::Relaxed);
+                info!(unlit, "toggled the preview's lighting");
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F12),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.screenshot.store(true, Ordering::Relaxed);
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F10),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.bracket.store(true, Ordering::Relaxed);
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F9),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(output) = self.output.lock().unwrap().as_mut() {
+                    output.request_replay();
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F4),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let connection = self.connection.lock().unwrap().clone();
+                if let Some(output) = self.output.lock().unwrap().as_mut() {
+                    output.cycle_cme_format(&connection);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::F11),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(output) = self.output.lock().unwrap().as_mut() {
+                    output.toggle_fullscreen();
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::Space),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let paused = !self.paused.fetch_xor(true, Ordering::Relaxed);
+                info!(paused, "toggled pause");
+                if let Some(output) = self.output.lock().unwrap().as_ref() {
+                    output.set_paused(paused);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::Tab),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.cycle_primary();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.reset_camera();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyL),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.aim_at_target();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyO),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                self.toggle_orbit();
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.set_looking(false);
+            }
+            WindowEvent::Focused(focused) => {
+                if !focused {
+                    self.set_looking(false);
+                }
+                if self.pause_on_blur {
+                    self.blurred.store(!focused, Ordering::Relaxed);
+                    if focused {
+                        info!("focused, rendering at the full rate again");
+                    } else {
+                        info!("unfocused, throttling rendering");
+                    }
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                if self.orbit_camera.is_none() {
+                    self.fly_cameras
+                        .lock()
+                        .unwrap()
+                        .primary_mut()
+                        .handle_key(key, state == ElementState::Pressed);
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let pressed = state == ElementState::Pressed;
+                self.update_orbit(|orbit_camera| orbit_camera.set_rotating(pressed));
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Right,
+                ..
+            } => {
+                let pressed = state == ElementState::Pressed;
+                if !self.update_orbit(|orbit_camera| orbit_camera.set_panning(pressed)) {
+                    self.set_looking(pressed);
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_LINE) as f32,
+                };
+                if !self.update_orbit(|orbit_camera| orbit_camera.handle_scroll(lines)) {
+                    self.zoom.lock().unwrap().handle_scroll(lines);
+                }
+            }
+            WindowEvent::RedrawRequested => {}
+            _ => {}
+        }
+    }
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let DeviceEvent::MouseMotion { delta } = event
+            && !self.update_orbit(|orbit_camera| orbit_camera.handle_mouse_motion(delta))
+        {
+            self.fly_cameras
+                .lock()
+                .unwrap()
+                .primary_mut()
+                .handle_mouse_motion(delta);
+        }
+    }
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        // the stardust loop can also shut down, e.g. when the model fails to load
+        if self.shutdown.is_cancelled() {
+            event_loop.exit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surface_format_prefers_srgb() {
+        let formats = [
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear),
+            (Format::R8G8B8A8_SRGB, ColorSpace::SrgbNonLinear),
+        ];
+        assert_eq!(
+            choose_surface_format(&formats, None).unwrap(),
+            (Format::R8G8B8A8_SRGB, ColorSpace::SrgbNonLinear)
+        );
+    }
+
+    #[test]
+    fn surface_format_falls_back_to_unorm() {
+        let formats = [(Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear)];
+        assert_eq!(
+            choose_surface_format(&formats, None).unwrap(),
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear)
+        );
+    }
+
+    #[test]
+    fn surface_format_pairs_the_requested_color_space() {
+        let formats = [
+            (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+            (Format::A2B10G10R10_UNORM_PACK32, ColorSpace::Hdr10St2084),
+            (Format::R16G16B16A16_SFLOAT, ColorSpace::Hdr10St2084),
+        ];
+        assert_eq!(
+            choose_surface_format(&formats, Some(ColorSpace::Hdr10St2084)).unwrap(),
+            (Format::R16G16B16A16_SFLOAT, ColorSpace::Hdr10St2084)
+        );
+    }
+
+    #[test]
+    fn surface_format_ignores_an_unsupported_color_space() {
+        let formats = [(Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear)];
+        assert_eq!(
+            choose_surface_format(&formats, Some(ColorSpace::Hdr10St2084)).unwrap(),
+            (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear)
+        );
+    }
+
+    #[test]
+    fn surface_format_fails_without_an_8_bit_format() {
+        let formats = [(Format::R5G6B5_UNORM_PACK16, ColorSpace::SrgbNonLinear)];
+        assert!(matches!(
+            choose_surface_format(&formats, None),
+            Err(SetupError::NoUsableSurfaceFormat(_))
+        ));
+    }
+
+    #[test]
+    fn image_count_is_double_buffered_by_default() {
+        assert_eq!(choose_image_count(None, 1, None), 2);
+        assert_eq!(choose_image_count(None, 3, None), 3);
+        assert_eq!(choose_image_count(None, 1, Some(1)), 1);
+    }
+
+    #[test]
+    fn image_count_is_clamped_to_the_surface() {
+        assert_eq!(choose_image_count(Some(3), 2, None), 3);
+        assert_eq!(choose_image_count(Some(5), 2, Some(4)), 4);
+        assert_eq!(choose_image_count(Some(1), 2, None), 2);
+    }
+
+    #[test]
+    fn composite_alpha_is_premultiplied_only_if_transparent() {
+        let supported = CompositeAlphas::OPAQUE | CompositeAlphas::PRE_MULTIPLIED;
+        assert_eq!(
+            choose_composite_alpha(supported, true),
+            CompositeAlpha::PreMultiplied
+        );
+        assert_eq!(
+            choose_composite_alpha(supported, false),
+            CompositeAlpha::Opaque
+        );
+        assert_eq!(
+            choose_composite_alpha(CompositeAlphas::OPAQUE, true),
+            CompositeAlpha::Opaque
+        );
+    }
+
+    #[test]
+    fn composite_alpha_falls_back_to_whatever_is_supported() {
+        assert_eq!(
+            choose_composite_alpha(
+                CompositeAlphas::INHERIT | CompositeAlphas::POST_MULTIPLIED,
+                false
+            ),
+            CompositeAlpha::Inherit
+        );
+        assert_eq!(
+            choose_composite_alpha(CompositeAlphas::POST_MULTIPLIED, false),
+            CompositeAlpha::PostMultiplied
+        );
+        assert_eq!(
+            choose_composite_alpha(CompositeAlphas::empty(), false),
+            CompositeAlpha::Opaque
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    descriptor_set::{
+        DescriptorSet, WriteDescriptorSet, allocator::StandardDescriptorSetAllocator,
+    },
+    device::Device,
+    image::{
+        Image,
+        sampler::{Sampler, SamplerCreateInfo},
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo, compute::ComputePipelineCreateInfo,
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+    },
+};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    error::{FrameContext, FrameError, SetupContext, SetupError},
+    frame::HDR_CME_FORMAT,
+};
+
+/// Pixels converted by a single invocation, one packed word per Y row and one for the UV row.
+const BLOCK_WIDTH: u32 = 4;
+const BLOCK_HEIGHT: u32 = 2;
+const WORKGROUP_SIZE: u32 = 8;
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+            #version 450
+
+            layout(local_size_x = 8, local_size_y = 8) in;
+
+            layout(push_constant) uniform PushConstants {
+                uvec2 view_size;
+                uint views;
+                uint hdr;
+            } pc;
+
+            layout(set = 0, binding = 0) uniform sampler2DArray image;
+            layout(set = 0, binding = 1) writeonly buffer Nv12 {
+                uint data[];
+            } nv12;
+
+            // gamma encoded R'G'B' of the side by side pixel at `pos`
+            vec3 fetch(uvec2 pos) {
+                ivec3 coord = ivec3(pos.x % pc.view_size.x, pos.y, pos.x / pc.view_size.x);
+                vec3 color = max(texelFetch(image, coord, 0).rgb, 0.0);
+                if (pc.hdr != 0) {
+                    // Reinhard, like the window's tone mapping pass
+                    color = color / (1.0 + color);
+                }
+                return mix(
+                    color * 12.92,
+                    1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055,
+                    greaterThan(color, vec3(0.0031308))
+                );
+            }
+
+            float luma(vec3 color) {
+                return dot(color, vec3(0.2126, 0.7152, 0.0722));
+            }
+
+            uint pack(vec4 bytes) {
+                uvec4 b = uvec4(clamp(round(bytes), 0.0, 255.0));
+                return b.x | (b.y << 8) | (b.z << 16) | (b.w << 24);
+            }
+
+            // BT.709 with limited range, every invocation converts a 4x2 block
+            void main() {
+                uint width = pc.view_size.x * pc.views;
+                uvec2 block = gl_GlobalInvocationID.xy;
+                uvec2 origin = block * uvec2(4, 2);
+                if (origin.x >= width || origin.y >= pc.view_size.y) {
+                    return;
+                }
+                vec4 top;
+                vec4 bottom;
+                vec3 chroma_sum[2] = vec3[2](vec3(0.0), vec3(0.0));
+                for (uint i = 0; i < 4; i++) {
+                    vec3 a = fetch(origin + uvec2(i, 0));
+                    vec3 b = fetch(origin + uvec2(i, 1));
+                    top[i] = luma(a);
+                    bottom[i] = luma(b);
+                    chroma_sum[i / 2] += a + b;
+                }
+                vec4 chroma;
+                for (uint i = 0; i < 2; i++) {
+                    vec3 color = chroma_sum[i] / 4.0;
+                    float y = luma(color);
+                    chroma[i * 2] = 128.0 + 224.0 * (color.b - y) / 1.8556;
+                    chroma[i * 2 + 1] = 128.0 + 224.0 * (color.r - y) / 1.5748;
+                }
+                uint row_words = width / 4;
+                uint y_plane_words = row_words * pc.view_size.y;
+                nv12.data[origin.y * row_words + block.x] = pack(16.0 + 219.0 * top);
+                nv12.data[(origin.y + 1) * row_words + block.x] = pack(16.0 + 219.0 * bottom);
+                nv12.data[y_plane_words + block.y * row_words + block.x] = pack(chroma);
+            }
+        ",
+    }
+}
+
+/// Whether the views of `size` can be converted, NV12 needs an even height and the packing
+/// needs a width divisible by 4.
+pub fn supports_size(size: PhysicalSize<u32>) -> bool {
+    size.width % BLOCK_WIDTH == 0 && size.height % BLOCK_HEIGHT == 0
+}
+
+/// Converts CME images to NV12 for video encoders, using the BT.709 matrix with limited range
+/// (Y in 16..=235, UV in 16..=240) on sRGB encoded values. Views are converted side by side
+/// into a single frame, the Y plane is followed directly by the interleaved UV plane.
+pub struct Nv12Converter {
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+    descriptor_alloc: Arc<StandardDescriptorSetAllocator>,
+    buffer: Subbuffer<[u32]>,
+    /// Size of a single view
+    size: PhysicalSize<u32>,
+    views: u32,
+}
+impl Nv12Converter {
+    /// `size` has to satisfy [`supports_size`].
+    pub fn new(
+        dev: &Arc<Device>,
+        memalloc: Arc<StandardMemoryAllocator>,
+        size: PhysicalSize<u32>,
+        views: u32,
+    ) -> Result<Self, SetupError> {
+        let stage = PipelineShaderStageCreateInfo::new(
+            cs::load(dev.clone())
+                .vk_context("loading the nv12 shader")?
+                .entry_point("main")
+                .unwrap(),
+        );
+        let layout = PipelineLayout::new(
+            dev.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(dev.clone())
+                .vk_context("creating the nv12 pipeline layout")?,
+        )
+        .vk_context("creating the nv12 pipeline layout")?;
+        let pipeline = ComputePipeline::new(
+            dev.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .vk_context("creating the nv12 pipeline")?;
+        let sampler = Sampler::new(dev.clone(), SamplerCreateInfo::default())
+            .vk_context("creating the nv12 sampler")?;
+        // 12 bits per pixel
+        let words = size.width as u64 * views as u64 * size.height as u64 * 3 / 2 / 4;
+        let buffer = Buffer::new_slice::<u32>(
+            memalloc,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            words,
+        )
+        .vk_context("allocating the nv12 buffer")?;
+        Ok(Self {
+            pipeline,
+            sampler,
+            descriptor_alloc: Arc::new(StandardDescriptorSetAllocator::new(
+                dev.clone(),
+                Default::default(),
+            )),
+            buffer,
+            size,
+            views,
+        })
+    }
+
+    /// Records the conversion of every layer of `image` into the NV12 buffer.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<Image>,
+    ) -> Result<(), FrameError> {
+        let hdr = image.format() == HDR_CME_FORMAT;
+        let view = ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .frame_context("creating the NV12 source view")?;
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_alloc.clone(),
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(0, view, self.sampler.clone()),
+                WriteDescriptorSet::buffer(1, self.buffer.clone()),
+            ],
+            [],
+        )
+        .frame_context("creating the NV12 descriptor set")?;
+        let blocks = [
+            (self.size.width * self.views).div_ceil(BLOCK_WIDTH),
+            self.size.height.div_ceil(BLOCK_HEIGHT),
+        ];
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .and_then(|builder| {
+                builder.bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    self.pipeline.layout().clone(),
+                    0,
+                    descriptor_set,
+                )
+            })
+            .and_then(|builder| {
+                builder.push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    cs::PushConstants {
+                        view_size: self.size.into(),
+                        views: self.views,
+                        hdr: hdr as u32,
+                    },
+                )
+            })
+            .frame_context("setting up the NV12 conversion")?;
+        unsafe {
+            builder.dispatch([
+                blocks[0].div_ceil(WORKGROUP_SIZE),
+                blocks[1].div_ceil(WORKGROUP_SIZE),
+                1,
+            ])
+        }
+        .frame_context("dispatching the NV12 conversion")?;
+        Ok(())
+    }
+
+    /// The raw NV12 frame, must only be called once the recorded conversion has completed.
+    pub fn frame(&self) -> io::Result<Vec<u8>> {
+        let data = self.buffer.read().map_err(io::Error::other)?;
+        Ok(data.iter().flat_map(|word| word.to_le_bytes()).collect())
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.frame()?)
+    }
+}